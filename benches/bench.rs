@@ -1,4 +1,3 @@
-#[macro_use]
 extern crate criterion;
 
 use criterion::*;
@@ -11,7 +10,9 @@ extern crate bytes;
 extern crate flumedb;
 extern crate tempfile;
 
+use flumedb::compressed_log::*;
 use flumedb::flume_log::FlumeLog;
+use flumedb::iter_at_offset::IterAtOffset;
 use flumedb::mem_log::MemLog;
 use flumedb::offset_log::*;
 use serde_json::{from_slice, Value};
@@ -21,6 +22,8 @@ const NUM_ENTRIES: usize = 10000;
 
 static DEFAULT_TEST_BUF: &[u8] = b"{\"value\": 1}";
 
+static MESSAGE_TEST_BUF: &[u8] = br#"{"key":"%Zo6Q1g9cVT0CXqGgiBRDu3S5cyyxPIhMf4vEp1mEmW8=.sha256","value":{"previous":"%uGJk2Aj6H9OnbTBNG9HhZBh1Z8hsR4aZWBmr6nE/dFU=.sha256","author":"@FCX/tsDLpubCPKKfIrw4gc+SQkHcaD17s7GI6i/ziWY=.ed25519","sequence":48,"timestamp":1553532843013,"hash":"sha256","content":{"type":"post","text":"hello from the benchmarks"},"signature":"e6LkAt5Eo8xZlsxz4z8fmxRFmUFfR8CMCwuR9XyUnoyQSIF/RN1DmGjrhTJk7D6Xx3tB6mfKBUI8DtUgEtBbCQ==.sig.ed25519"},"timestamp":1553532843100}"#;
+
 fn default_test_bufs() -> Vec<&'static [u8]> {
     vec![DEFAULT_TEST_BUF; NUM_ENTRIES]
 }
@@ -76,7 +79,7 @@ fn offset_log_append_batch(c: &mut Criterion) {
             temp_offset_log,
             |mut log| {
                 for chunk in test_bufs.chunks(100) {
-                    let offsets = log.append_batch(&chunk).unwrap();
+                    let offsets = log.append_batch(chunk).unwrap();
                     assert_eq!(offsets.len(), chunk.len());
                 }
             },
@@ -99,6 +102,20 @@ fn offset_log_get(c: &mut Criterion) {
     });
 }
 
+fn offset_log_get_many(c: &mut Criterion) {
+    let mut log = temp_offset_log();
+    let offsets = log.append_batch(&default_test_bufs()).unwrap();
+    let wanted: Vec<u64> = offsets.iter().rev().step_by(3).cloned().collect();
+
+    c.bench_function("offset log get many", move |b| {
+        b.iter(|| {
+            for result in log.get_many(&wanted) {
+                assert_eq!(result.unwrap().len(), DEFAULT_TEST_BUF.len());
+            }
+        })
+    });
+}
+
 fn offset_log_iter(c: &mut Criterion) {
     // Forward
     let mut log = temp_offset_log();
@@ -106,7 +123,7 @@ fn offset_log_iter(c: &mut Criterion) {
 
     c.bench_function("offset log iter forward", move |b| {
         b.iter_batched(
-            || log.iter(),
+            || log.bidir_iter(),
             |mut iter| {
                 let count = iter.forward().count();
                 assert_eq!(count, offsets.len());
@@ -121,7 +138,7 @@ fn offset_log_iter(c: &mut Criterion) {
 
     c.bench_function("offset log iter backward", move |b| {
         b.iter_batched(
-            || log.iter_at_offset(log.end()),
+            || log.bidir_iter_at_offset(log.end()),
             |mut iter| {
                 let count = iter.backward().count();
                 assert_eq!(count, offsets.len());
@@ -136,7 +153,7 @@ fn offset_log_iter(c: &mut Criterion) {
 
     c.bench_function("offset log iter forward and json decode", move |b| {
         b.iter_batched(
-            || log.iter(),
+            || log.bidir_iter(),
             |mut iter| {
                 let sum: u64 = iter
                     .forward()
@@ -182,7 +199,7 @@ fn mem_log_append(c: &mut Criterion) {
                 .map(|_| log.append(DEFAULT_TEST_BUF).unwrap())
                 .collect();
 
-            assert_eq!(offsets.len(), NUM_ENTRIES as usize);
+            assert_eq!(offsets.len(), NUM_ENTRIES);
         })
     });
 }
@@ -198,12 +215,9 @@ fn mem_log_iter(c: &mut Criterion) {
         b.iter(|| {
             let sum: u64 = log
                 .into_iter()
-                .map(|val| from_slice(&val).unwrap())
+                .map(|val| from_slice(&val.data).unwrap())
                 .map(|val: Value| match val["value"] {
-                    Value::Number(ref num) => {
-                        let result = num.as_u64().unwrap();
-                        result
-                    }
+                    Value::Number(ref num) => num.as_u64().unwrap(),
                     _ => panic!(),
                 })
                 .sum();
@@ -213,10 +227,72 @@ fn mem_log_iter(c: &mut Criterion) {
     });
 }
 
+fn compressions() -> Vec<(&'static str, Compression)> {
+    vec![
+        ("none", Compression::None),
+        ("lz4", Compression::Lz4),
+        ("zstd", Compression::Zstd { level: 3 }),
+    ]
+}
+
+fn temp_compressed_log(compression: Compression) -> CompressedLog<OffsetLog<u32>> {
+    CompressedLog::new(temp_offset_log(), compression)
+}
+
+fn compressed_log_append(c: &mut Criterion) {
+    for (name, compression) in compressions() {
+        c.bench_function(&format!("compressed log append {}", name), move |b| {
+            b.iter_batched(
+                || temp_compressed_log(compression),
+                |mut log| {
+                    for _ in 0..NUM_ENTRIES {
+                        log.append(MESSAGE_TEST_BUF).unwrap();
+                    }
+                },
+                BatchSize::SmallInput,
+            );
+        });
+    }
+}
+
+fn compressed_log_get(c: &mut Criterion) {
+    for (name, compression) in compressions() {
+        let mut log = temp_compressed_log(compression);
+        let offsets: Vec<u64> = (0..NUM_ENTRIES)
+            .map(|_| log.append(MESSAGE_TEST_BUF).unwrap())
+            .collect();
+
+        c.bench_function(&format!("compressed log get {}", name), move |b| {
+            b.iter(|| {
+                for offset in offsets.iter() {
+                    let result = log.get(*offset).unwrap();
+                    assert_eq!(result.len(), MESSAGE_TEST_BUF.len());
+                }
+            })
+        });
+    }
+}
+
+fn compressed_log_iter(c: &mut Criterion) {
+    for (name, compression) in compressions() {
+        let mut log = temp_compressed_log(compression);
+        (0..NUM_ENTRIES).for_each(|_| {
+            log.append(MESSAGE_TEST_BUF).unwrap();
+        });
+
+        c.bench_function(&format!("compressed log iter {}", name), move |b| {
+            b.iter(|| {
+                let count = log.iter_at_offset(0).count();
+                assert_eq!(count, NUM_ENTRIES);
+            })
+        });
+    }
+}
+
 criterion_group! {
 name = offset_log;
 config = Criterion::default().sample_size(10);
-targets = offset_log_get, offset_log_append, offset_log_append_batch, offset_log_iter, offset_log_decode
+targets = offset_log_get, offset_log_get_many, offset_log_append, offset_log_append_batch, offset_log_iter, offset_log_decode
 }
 
 criterion_group! {
@@ -225,4 +301,10 @@ config = Criterion::default().sample_size(10);
 targets = mem_log_get, mem_log_append, mem_log_iter
 }
 
-criterion_main!(offset_log, mem_log);
+criterion_group! {
+name = compressed_log;
+config = Criterion::default().sample_size(10);
+targets = compressed_log_append, compressed_log_get, compressed_log_iter
+}
+
+criterion_main!(offset_log, mem_log, compressed_log);
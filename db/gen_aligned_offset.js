@@ -0,0 +1,41 @@
+var Log = require('async-append-only-log')
+var bipf = require('bipf')
+var fs = require('fs')
+
+// A small block size, so that the records span a few blocks. Records are
+// BIPF, as ssb-db2 stores them.
+var dir = '../test_vecs/aligned_offset/'
+var log = Log(dir + 'js_log.bipf', {blockSize: 256})
+
+const NUM_ELEMENTS = 20
+var offsets = []
+
+function append (value, cb) {
+  log.append(bipf.allocAndEncode({value: value}), function (err, offset) {
+    if (err) throw err
+    offsets.push(offset)
+    cb()
+  })
+}
+
+function appendFrom (i, cb) {
+  if (i === NUM_ELEMENTS) return cb()
+  // A record too long for the rest of the first block.
+  if (i === 10) return append('x'.repeat(200), () => append(i, () => appendFrom(i + 1, cb)))
+  append(i, () => appendFrom(i + 1, cb))
+}
+
+appendFrom(0, function () {
+  log.onDrain(function () {
+    log.del(offsets[5], function (err) {
+      if (err) throw err
+      log.onDeletesFlushed(function () {
+        log.close(function () {
+          // The offsets the log gave each record, in order, to check the
+          // Rust reader against.
+          fs.writeFileSync(dir + 'js_log_offsets.json', JSON.stringify(offsets))
+        })
+      })
+    })
+  })
+})
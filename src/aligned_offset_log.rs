@@ -0,0 +1,549 @@
+use crate::file_lock::{lock_exclusive, lock_shared};
+use crate::flume_log::*;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use crate::offset_log::ReadResult;
+use buffered_offset_reader::{OffsetRead, OffsetWrite};
+use byteorder::{ByteOrder, LittleEndian};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use thiserror::Error;
+
+/// The block size the JS `async-append-only-log` uses by default.
+pub const DEFAULT_BLOCK_SIZE: u64 = 64 * 1024;
+
+// Every record starts with its data length, as a little-endian u16. A
+// length of zero marks the end of the records in a block.
+const HEADER_SIZE: u64 = 2;
+
+#[derive(Debug, Error)]
+pub enum AlignedOffsetLogError {
+    #[error("Records can't be empty")]
+    EmptyRecord {},
+
+    #[error("Record of {size} bytes is larger than the maximum of {max} bytes")]
+    RecordTooLarge { size: usize, max: usize },
+
+    #[error("Block size {block_size} is outside the supported range")]
+    InvalidBlockSize { block_size: u64 },
+
+    #[error("Incorrect record length detected, log file might be corrupt")]
+    CorruptLogFile {},
+}
+
+/// A log in a block-aligned format modelled on the JS
+/// `async-append-only-log` (formerly `flumelog-aligned-offset`), which
+/// `ssb-db2` keeps its messages in.
+///
+/// It's written from the JS module's description of its format, and hasn't
+/// yet been checked against files the JS module wrote, so don't rely on the
+/// two reading each other's logs. The ignored `read_js_log` test does that
+/// check, once `db/gen_aligned_offset.js` has written its fixture.
+///
+/// The file is a series of fixed-size blocks. Each block holds records of
+/// `[data length: u16 LE, data]`, followed by zeroes; records never cross
+/// a block boundary. A record's sequence is its byte offset in the file.
+/// Deleted records keep their length but have their data zeroed: they read
+/// back as zeroes, and iteration skips them, like `ssb-db2` does.
+///
+/// Because a zero length marks the end of a block, records can't be empty,
+/// and must fit in a block along with that marker.
+pub struct AlignedOffsetLog {
+    file: File,
+    block_size: u64,
+    end: u64,
+    last_offset: Option<u64>,
+}
+
+impl AlignedOffsetLog {
+    /// Opens (or creates) the log file at `path` for reading and writing,
+    /// with blocks of `block_size` bytes.
+    ///
+    /// Takes an exclusive advisory lock on the file, and fails with
+    /// `FlumeLogError::LogLocked` if another handle already has it open.
+    pub fn new<P: AsRef<Path>>(path: P, block_size: u64) -> Result<AlignedOffsetLog, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        lock_exclusive(&file, path.as_ref())?;
+
+        AlignedOffsetLog::from_file(file, block_size)
+    }
+
+    /// Opens the log file at `path`, with blocks of `block_size` bytes, for
+    /// reading.
+    ///
+    /// Takes a shared advisory lock on the file, so any number of readers can
+    /// coexist, but not alongside a writer opened with [`AlignedOffsetLog::new`].
+    pub fn open_read_only<P: AsRef<Path>>(
+        path: P,
+        block_size: u64,
+    ) -> Result<AlignedOffsetLog, Error> {
+        let file = OpenOptions::new().read(true).open(&path)?;
+        lock_shared(&file, path.as_ref())?;
+
+        AlignedOffsetLog::from_file(file, block_size)
+    }
+
+    pub fn from_file(mut file: File, block_size: u64) -> Result<AlignedOffsetLog, Error> {
+        // Lengths are u16s, and a block must fit a record and the end marker.
+        if block_size <= 2 * HEADER_SIZE || block_size > u16::MAX as u64 + 2 * HEADER_SIZE {
+            return Err(aligned_error(
+                0,
+                AlignedOffsetLogError::InvalidBlockSize { block_size },
+            ));
+        }
+        let file_length = file.seek(SeekFrom::End(0))?;
+
+        let mut log = AlignedOffsetLog {
+            file,
+            block_size,
+            end: 0,
+            last_offset: None,
+        };
+        if file_length > 0 {
+            // Only the last block has room for more records.
+            let block_start = (file_length - 1) / block_size * block_size;
+            let block = log.read_block(block_start)?;
+            let mut records = BlockRecords::new(&block, block_start);
+            for record in &mut records {
+                log.last_offset = Some(record?.0);
+            }
+            log.end = records.end();
+        }
+        Ok(log)
+    }
+
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// The offset the next record will be written at, or the start of the
+    /// next block if it doesn't fit in the current one.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// The largest record this log can hold.
+    pub fn max_record_size(&self) -> usize {
+        (self.block_size - 2 * HEADER_SIZE) as usize
+    }
+
+    /// Reads the record at `offset`, failing with
+    /// `FlumeLogError::SequenceNotFound` if `offset` isn't the start of a
+    /// record. `next` is the offset just after the record.
+    pub fn read(&self, offset: u64) -> Result<ReadResult, Error> {
+        let not_found = || FlumeLogError::SequenceNotFound { sequence: offset }.into();
+        if offset >= self.end {
+            return Err(not_found());
+        }
+        // Records can only be found by walking their block from the start.
+        let block_start = offset / self.block_size * self.block_size;
+        let block = self.read_block(block_start)?;
+        for record in BlockRecords::new(&block, block_start) {
+            let (record_offset, data) = record?;
+            if record_offset == offset {
+                return Ok(ReadResult {
+                    entry: LogEntry {
+                        offset,
+                        data: data.to_vec(),
+                    },
+                    next: offset + HEADER_SIZE + data.len() as u64,
+                });
+            }
+            if record_offset > offset {
+                break;
+            }
+        }
+        Err(not_found())
+    }
+
+    pub fn iter(&self) -> AlignedOffsetLogIter {
+        self.iter_at_offset(0)
+    }
+
+    /// Reads the block that starts at `block_start`. The parts of it past
+    /// the end of the file read as zeroes.
+    fn read_block(&self, block_start: u64) -> Result<Vec<u8>, Error> {
+        read_block(&self.file, block_start, self.block_size)
+    }
+}
+
+impl NextSequence for AlignedOffsetLog {
+    /// Leaves room for the end-of-block marker, starting a new block if the
+    /// record doesn't fit in the current one.
+    fn next_sequence(&self, len: usize) -> u64 {
+        let block_end = (self.end / self.block_size + 1) * self.block_size;
+        if self.end + HEADER_SIZE + len as u64 + HEADER_SIZE > block_end {
+            block_end
+        } else {
+            self.end
+        }
+    }
+}
+
+impl FlumeLog for AlignedOffsetLog {
+    fn get(&self, seq_num: u64) -> Result<Vec<u8>, Error> {
+        self.read(seq_num).map(|r| r.entry.data)
+    }
+
+    fn latest(&self) -> Option<u64> {
+        self.last_offset
+    }
+
+    fn append(&mut self, buff: &[u8]) -> Result<u64, Error> {
+        if buff.is_empty() {
+            return Err(aligned_error(
+                self.end,
+                AlignedOffsetLogError::EmptyRecord {},
+            ));
+        }
+        if buff.len() > self.max_record_size() {
+            return Err(aligned_error(
+                self.end,
+                AlignedOffsetLogError::RecordTooLarge {
+                    size: buff.len(),
+                    max: self.max_record_size(),
+                },
+            ));
+        }
+
+        let mut record = vec![0; HEADER_SIZE as usize];
+        LittleEndian::write_u16(&mut record, buff.len() as u16);
+        record.extend_from_slice(buff);
+
+        // Start a new block written out in full, so the file stays a whole
+        // number of blocks.
+        let offset = self.next_sequence(buff.len());
+        if offset.is_multiple_of(self.block_size) {
+            record.resize(self.block_size as usize, 0);
+        }
+        self.file.write_at(&record, offset)?;
+
+        self.end = offset + HEADER_SIZE + buff.len() as u64;
+        self.last_offset = Some(offset);
+        Ok(offset)
+    }
+
+    /// Overwrites the record's data with zeroes, leaving its length intact,
+    /// which is how the JS log deletes records.
+    fn clear(&mut self, seq_num: u64) -> Result<(), Error> {
+        let r = self.read(seq_num)?;
+        let zeroes = vec![0; r.entry.data.len()];
+        self.file.write_at(&zeroes, seq_num + HEADER_SIZE)?;
+        Ok(())
+    }
+}
+
+impl Overwrite for AlignedOffsetLog {
+    fn overwrite(&mut self, seq: u64, data: &[u8]) -> Result<(), Error> {
+        let r = self.read(seq)?;
+        check_overwrite_length(seq, &r.entry.data, data)?;
+        self.file.write_at(data, seq + HEADER_SIZE)?;
+        Ok(())
+    }
+}
+
+impl IterAtOffset<AlignedOffsetLogIter> for AlignedOffsetLog {
+    fn iter_at_offset(&self, offset: u64) -> AlignedOffsetLogIter {
+        AlignedOffsetLogIter {
+            file: self.file.try_clone().unwrap(),
+            block_size: self.block_size,
+            end: self.end,
+            block: Vec::new(),
+            block_start: None,
+            next: offset,
+        }
+    }
+}
+
+/// Iterates over the records of an [`AlignedOffsetLog`], a block at a time,
+/// skipping deleted records.
+pub struct AlignedOffsetLogIter {
+    file: File,
+    block_size: u64,
+    end: u64,
+    block: Vec<u8>,
+    block_start: Option<u64>,
+    next: u64,
+}
+
+impl Iterator for AlignedOffsetLogIter {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<LogEntry> {
+        loop {
+            if self.next >= self.end {
+                return None;
+            }
+            let block_start = self.next / self.block_size * self.block_size;
+            if self.block_start != Some(block_start) {
+                self.block = read_block(&self.file, block_start, self.block_size).ok()?;
+                self.block_start = Some(block_start);
+            }
+
+            let in_block = (self.next - block_start) as usize;
+            let data = match record_at(&self.block, in_block).ok()? {
+                Some(data) => data,
+                None => {
+                    self.next = block_start + self.block_size;
+                    continue;
+                }
+            };
+            let offset = self.next;
+            self.next += HEADER_SIZE + data.len() as u64;
+            if data.iter().any(|b| *b != 0) {
+                return Some(LogEntry {
+                    offset,
+                    data: data.to_vec(),
+                });
+            }
+        }
+    }
+}
+
+/// The records in a block, as pairs of offset in the log and data.
+struct BlockRecords<'a> {
+    block: &'a [u8],
+    block_start: u64,
+    in_block: usize,
+    done: bool,
+}
+
+impl<'a> BlockRecords<'a> {
+    fn new(block: &'a [u8], block_start: u64) -> BlockRecords<'a> {
+        BlockRecords {
+            block,
+            block_start,
+            in_block: 0,
+            done: false,
+        }
+    }
+
+    /// The offset just after the last record in the block. Only meaningful
+    /// once all the records have been iterated over.
+    fn end(&self) -> u64 {
+        self.block_start + self.in_block as u64
+    }
+}
+
+impl<'a> Iterator for BlockRecords<'a> {
+    type Item = Result<(u64, &'a [u8]), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match record_at(self.block, self.in_block) {
+            Ok(Some(data)) => {
+                let offset = self.block_start + self.in_block as u64;
+                self.in_block += HEADER_SIZE as usize + data.len();
+                Some(Ok((offset, data)))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(source) => {
+                self.done = true;
+                let offset = self.block_start + self.in_block as u64;
+                Some(Err(aligned_error(offset, source)))
+            }
+        }
+    }
+}
+
+/// The data of the record at `in_block` in `block`, or `None` if the block's
+/// records end there.
+fn record_at(block: &[u8], in_block: usize) -> Result<Option<&[u8]>, AlignedOffsetLogError> {
+    let data_start = in_block + HEADER_SIZE as usize;
+    if data_start > block.len() {
+        return Ok(None);
+    }
+    let len = LittleEndian::read_u16(&block[in_block..data_start]) as usize;
+    if len == 0 {
+        return Ok(None);
+    }
+    block
+        .get(data_start..data_start + len)
+        .map(Some)
+        .ok_or(AlignedOffsetLogError::CorruptLogFile {})
+}
+
+fn read_block(file: &File, block_start: u64, block_size: u64) -> Result<Vec<u8>, Error> {
+    let mut block = vec![0; block_size as usize];
+    let mut filled = 0;
+    while filled < block.len() {
+        match file.read_at(&mut block[filled..], block_start + filled as u64) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(block)
+}
+
+fn aligned_error(offset: u64, source: AlignedOffsetLogError) -> Error {
+    Error::AlignedOffsetLog { offset, source }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::aligned_offset_log::*;
+    use serde_json::{json, Value};
+    use std::path::PathBuf;
+    use tempfile::{tempdir, tempfile};
+
+    fn temp_log(block_size: u64) -> AlignedOffsetLog {
+        AlignedOffsetLog::from_file(tempfile().unwrap(), block_size).unwrap()
+    }
+
+    fn fixture_path() -> PathBuf {
+        test_vec("log.bipf")
+    }
+
+    fn test_vec(name: &str) -> PathBuf {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("test_vecs/aligned_offset");
+        path.push(name);
+        path
+    }
+
+    #[test]
+    fn append_and_read() -> Result<(), Error> {
+        let mut log = temp_log(16);
+        assert_eq!(log.latest(), None);
+        assert!(log.iter().next().is_none());
+
+        // A block of 16 fits a 10 byte record, but not two 5 byte ones.
+        let a = log.append(b"0123456789")?;
+        let b = log.append(b"abcde")?;
+        let c = log.append(b"fghij")?;
+        assert_eq!((a, b, c), (0, 16, 23));
+        assert_eq!(log.latest(), Some(c));
+        assert_eq!(log.get(b)?, b"abcde");
+
+        for seq in &[1, 12, 17, 30, 32, 1000] {
+            match log.get(*seq) {
+                Err(Error::Log(FlumeLogError::SequenceNotFound { sequence })) => {
+                    assert_eq!(sequence, *seq)
+                }
+                r => panic!("expected SequenceNotFound for {}, got {:?}", seq, r),
+            }
+        }
+
+        let offsets: Vec<u64> = log.iter().map(|e| e.offset).collect();
+        assert_eq!(offsets, &[a, b, c]);
+        let offsets: Vec<u64> = log.iter_at_offset(b).map(|e| e.offset).collect();
+        assert_eq!(offsets, &[b, c]);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_records_that_dont_fit() {
+        let mut log = temp_log(16);
+        assert_eq!(log.max_record_size(), 12);
+        log.append(b"0123456789ab").unwrap();
+
+        match log.append(b"0123456789abc") {
+            Err(Error::AlignedOffsetLog {
+                source: AlignedOffsetLogError::RecordTooLarge { size: 13, max: 12 },
+                ..
+            }) => {}
+            r => panic!("expected RecordTooLarge, got {:?}", r),
+        }
+        assert!(matches!(
+            log.append(b""),
+            Err(Error::AlignedOffsetLog {
+                source: AlignedOffsetLogError::EmptyRecord {},
+                ..
+            })
+        ));
+        assert!(AlignedOffsetLog::from_file(tempfile().unwrap(), 4).is_err());
+        assert_eq!(log.latest(), Some(0));
+    }
+
+    #[test]
+    fn clear_and_reopen() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log.bipf");
+        let mut log = AlignedOffsetLog::new(&path, 32)?;
+        let seqs: Vec<u64> = (0..10)
+            .map(|i| log.append(format!("record {}", i).as_bytes()).unwrap())
+            .collect();
+        log.clear(seqs[3])?;
+        assert_eq!(log.get(seqs[3])?, vec![0; 8]);
+        drop(log);
+
+        assert!(std::fs::metadata(&path)?.len().is_multiple_of(32));
+        let mut log = AlignedOffsetLog::new(&path, 32)?;
+        assert_eq!(log.latest(), Some(seqs[9]));
+        let next = log.append(b"record 10")?;
+        assert!(next > seqs[9]);
+
+        let data: Vec<Vec<u8>> = log.iter().map(|e| e.data).collect();
+        assert_eq!(data.len(), 10);
+        assert_eq!(data[3], b"record 4");
+        assert_eq!(data[9], b"record 10");
+        Ok(())
+    }
+
+    #[test]
+    fn read_fixture_log() -> Result<(), Error> {
+        // Written by hand, with 256 byte blocks, to hold what
+        // db/gen_aligned_offset.js should write; it hasn't been regenerated
+        // with that script. The record {"value": 5} has been deleted.
+        let log = AlignedOffsetLog::open_read_only(fixture_path(), 256)?;
+
+        let entries: Vec<LogEntry> = log.iter().collect();
+        let values: Vec<Value> = entries
+            .iter()
+            .map(|e| serde_json::from_slice::<Value>(&e.data).unwrap()["value"].clone())
+            .collect();
+        let mut expected: Vec<Value> = (0..10).filter(|i| *i != 5).map(|i| json!(i)).collect();
+        expected.push(json!("x".repeat(200)));
+        expected.extend((10..20).map(|i| json!(i)));
+        assert_eq!(values, expected);
+
+        // The long record didn't fit in the first block.
+        assert_eq!(entries[9].offset, 256);
+        assert_eq!(entries[10].offset, 256 + 214);
+        assert_eq!(log.latest(), Some(entries.last().unwrap().offset));
+        assert!(log.get(entries[4].offset + 13)?.iter().all(|b| *b == 0));
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "needs the fixture db/gen_aligned_offset.js writes"]
+    fn read_js_log() -> Result<(), Error> {
+        // Written by db/gen_aligned_offset.js, with 256 byte blocks and BIPF
+        // records, along with the offset the JS log gave each record. The
+        // record {"value": 5} has been deleted.
+        let log = AlignedOffsetLog::open_read_only(test_vec("js_log.bipf"), 256)?;
+        let offsets = std::fs::read(test_vec("js_log_offsets.json"))?;
+        let mut offsets: Vec<u64> = serde_json::from_slice(&offsets)?;
+        offsets.remove(5);
+
+        let entries: Vec<LogEntry> = log.iter().collect();
+        let values = entries
+            .iter()
+            .map(|e| Ok(crate::bipf::decode(&e.data)?["value"].clone()))
+            .collect::<Result<Vec<Value>, Error>>()?;
+        let mut expected: Vec<Value> = (0..10).filter(|i| *i != 5).map(|i| json!(i)).collect();
+        expected.push(json!("x".repeat(200)));
+        expected.extend((10..20).map(|i| json!(i)));
+        assert_eq!(values, expected);
+
+        let read: Vec<u64> = entries.iter().map(|e| e.offset).collect();
+        assert_eq!(read, offsets);
+        assert_eq!(entries[9].offset % 256, 0);
+        assert_eq!(log.latest(), offsets.last().cloned());
+        Ok(())
+    }
+}
@@ -0,0 +1,282 @@
+//! Async adaptors for logs, enabled with the `async` feature.
+//!
+//! All file I/O is done with `tokio::task::spawn_blocking`, so none of these
+//! futures or streams block the async runtime's worker threads.
+
+use crate::flume_log::*;
+use crate::go_offset_log::GoOffsetLog;
+use crate::log_entry::LogEntry;
+use crate::mem_log::MemLog;
+use crate::offset_log::OffsetLog;
+use futures::stream::{self, Stream, StreamExt};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+use tokio::task::spawn_blocking;
+
+// How many entries a stream reads per trip to the blocking thread pool.
+const BATCH_SIZE: usize = 256;
+
+/// Sequential reads for logs that can be streamed by an [`AsyncLog`].
+pub trait ReadNext {
+    /// Reads the entry at `seq`, returning it along with the sequence of the
+    /// entry that follows it, or `None` if there is no entry at `seq` yet.
+    fn read_next(&self, seq: Sequence) -> Result<Option<(LogEntry, Sequence)>, Error>;
+}
+
+impl<ByteType> ReadNext for OffsetLog<ByteType> {
+    fn read_next(&self, seq: Sequence) -> Result<Option<(LogEntry, Sequence)>, Error> {
+        if seq >= self.end() {
+            return Ok(None);
+        }
+        let r = self.read(seq)?;
+        Ok(Some((r.entry, r.next)))
+    }
+}
+
+impl ReadNext for GoOffsetLog {
+    fn read_next(&self, seq: Sequence) -> Result<Option<(LogEntry, Sequence)>, Error> {
+        if seq >= self.end() {
+            return Ok(None);
+        }
+        let r = self.read(seq)?;
+        Ok(Some((r.entry, r.next)))
+    }
+}
+
+impl ReadNext for MemLog {
+    fn read_next(&self, seq: Sequence) -> Result<Option<(LogEntry, Sequence)>, Error> {
+        match self.latest() {
+            Some(latest) if seq <= latest => {
+                let data = self.get(seq)?;
+                Ok(Some((LogEntry { offset: seq, data }, seq + 1)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Wraps a log so that it can be read, streamed and appended to from async code.
+///
+/// Live streams are woken by appends made through this wrapper; appends made
+/// directly to the underlying log aren't seen until the next one that is.
+pub struct AsyncLog<L> {
+    log: Arc<Mutex<L>>,
+    appended: watch::Sender<u64>,
+}
+
+impl<L> Clone for AsyncLog<L> {
+    fn clone(&self) -> Self {
+        AsyncLog {
+            log: self.log.clone(),
+            appended: self.appended.clone(),
+        }
+    }
+}
+
+impl<L: ReadNext + Send + 'static> AsyncLog<L> {
+    pub fn new(log: L) -> AsyncLog<L> {
+        let (appended, _) = watch::channel(0);
+        AsyncLog {
+            log: Arc::new(Mutex::new(log)),
+            appended,
+        }
+    }
+
+    /// Runs `f` with the underlying log on the blocking thread pool.
+    pub async fn with_log<F, T>(&self, f: F) -> Result<T, Error>
+    where
+        F: FnOnce(&mut L) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let log = self.log.clone();
+        Ok(spawn_blocking(move || f(&mut log.lock().unwrap())).await?)
+    }
+
+    /// Streams every entry currently in the log, then ends.
+    pub fn stream(&self) -> impl Stream<Item = Result<LogEntry, Error>> {
+        self.stream_from(0, false)
+    }
+
+    /// Streams entries starting at `seq`.
+    ///
+    /// If `live` is true, the stream doesn't end when it reaches the end of
+    /// the log, but waits for new entries to be appended. A live stream ends
+    /// once every handle to this `AsyncLog` has been dropped.
+    pub fn stream_from(
+        &self,
+        seq: Sequence,
+        live: bool,
+    ) -> impl Stream<Item = Result<LogEntry, Error>> {
+        let cursor = Cursor {
+            log: self.log.clone(),
+            next: seq,
+            live,
+            done: false,
+            appended: self.appended.subscribe(),
+        };
+        stream::unfold(cursor, next_batch).flat_map(stream::iter)
+    }
+}
+
+impl<L: FlumeLog + ReadNext + Send + 'static> AsyncLog<L> {
+    pub async fn get(&self, seq: Sequence) -> Result<Vec<u8>, Error> {
+        self.with_log(move |log| log.get(seq)).await?
+    }
+
+    pub async fn latest(&self) -> Result<Option<Sequence>, Error> {
+        self.with_log(|log| log.latest()).await
+    }
+
+    /// Appends `buff` to the log and wakes any live streams.
+    pub async fn append(&self, buff: &[u8]) -> Result<Sequence, Error> {
+        let buff = buff.to_vec();
+        let seq = self.with_log(move |log| log.append(&buff)).await??;
+        self.appended.send_modify(|count| *count += 1);
+        Ok(seq)
+    }
+}
+
+struct Cursor<L> {
+    log: Arc<Mutex<L>>,
+    next: Sequence,
+    live: bool,
+    done: bool,
+    appended: watch::Receiver<u64>,
+}
+
+async fn next_batch<L>(mut cursor: Cursor<L>) -> Option<(Vec<Result<LogEntry, Error>>, Cursor<L>)>
+where
+    L: ReadNext + Send + 'static,
+{
+    while !cursor.done {
+        // Mark the current version as seen *before* reading, so that an
+        // append that lands after our read still wakes us up.
+        cursor.appended.borrow_and_update();
+
+        let log = cursor.log.clone();
+        let start = cursor.next;
+        let (entries, next, err) = match spawn_blocking(move || read_batch(&log, start)).await {
+            Ok(batch) => batch,
+            Err(e) => (vec![], start, Some(e.into())),
+        };
+        cursor.next = next;
+
+        let mut batch: Vec<Result<LogEntry, Error>> = entries.into_iter().map(Ok).collect();
+        if let Some(err) = err {
+            cursor.done = true;
+            batch.push(Err(err));
+        }
+        if !batch.is_empty() {
+            return Some((batch, cursor));
+        }
+
+        if !cursor.live || cursor.appended.changed().await.is_err() {
+            cursor.done = true;
+        }
+    }
+    None
+}
+
+fn read_batch<L: ReadNext>(
+    log: &Mutex<L>,
+    mut seq: Sequence,
+) -> (Vec<LogEntry>, Sequence, Option<Error>) {
+    let log = log.lock().unwrap();
+    let mut entries = Vec::new();
+    while entries.len() < BATCH_SIZE {
+        match log.read_next(seq) {
+            Ok(Some((entry, next))) => {
+                entries.push(entry);
+                seq = next;
+            }
+            Ok(None) => break,
+            Err(err) => return (entries, seq, Some(err)),
+        }
+    }
+    (entries, seq, None)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::async_log::*;
+    use crate::offset_log::OffsetLog;
+    use futures::stream::StreamExt;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use tempfile::tempfile;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn append_and_get() -> Result<(), Error> {
+        let log = AsyncLog::new(OffsetLog::<u32>::from_file(tempfile()?)?);
+        let a = log.append(b"abc").await?;
+        let b = log.append(b"def").await?;
+
+        assert_eq!(log.get(a).await?, b"abc");
+        assert_eq!(log.get(b).await?, b"def");
+        assert_eq!(log.latest().await?, Some(b));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stream_offset_log() -> Result<(), Error> {
+        let mut log = OffsetLog::<u32>::from_file(tempfile()?)?;
+        let bufs: Vec<Vec<u8>> = (0..1000u32).map(|i| i.to_string().into_bytes()).collect();
+        let offsets = log.append_batch(&bufs)?;
+        let log = AsyncLog::new(log);
+
+        let entries: Vec<LogEntry> = log.stream().map(|r| r.unwrap()).collect::<Vec<_>>().await;
+        assert_eq!(entries.len(), bufs.len());
+        assert_eq!(entries[999].data, b"999");
+
+        let entries: Vec<LogEntry> = log
+            .stream_from(offsets[500], false)
+            .map(|r| r.unwrap())
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(entries.len(), 500);
+        assert_eq!(entries[0].offset, offsets[500]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stream_go_offset_log() -> Result<(), Error> {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("test_vecs/four_ssb_messages");
+        let log = AsyncLog::new(GoOffsetLog::open_read_only(d)?);
+
+        let count = log.stream().map(|r| r.unwrap()).count().await;
+        assert_eq!(count, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn live_stream_mem_log() -> Result<(), Error> {
+        let log = AsyncLog::new(MemLog::new());
+        log.append(b"abc").await?;
+
+        let mut live = Box::pin(log.stream_from(0, true));
+        assert_eq!(live.next().await.unwrap()?.data, b"abc");
+
+        let tail = timeout(Duration::from_millis(50), live.next()).await;
+        assert!(
+            tail.is_err(),
+            "live stream shouldn't end at the end of the log"
+        );
+
+        let writer = log.clone();
+        tokio::spawn(async move {
+            writer.append(b"def").await.unwrap();
+            writer.append(b"ghi").await.unwrap();
+        });
+
+        let e = live.next().await.unwrap()?;
+        assert_eq!((e.offset, e.data), (1, b"def".to_vec()));
+        let e = live.next().await.unwrap()?;
+        assert_eq!((e.offset, e.data), (2, b"ghi".to_vec()));
+
+        drop(log);
+        assert!(live.next().await.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+use crate::flume_log::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+/// Replaces the contents of `path` with `bytes`, so that a crash part way
+/// through leaves either the old contents or the new ones, like the JS
+/// `atomic-file` module that flume views use.
+///
+/// The new contents are synced to disk before they replace the old ones,
+/// and the directory is synced after, so that the rename can't reach the
+/// disk ahead of the data it points to.
+pub(crate) fn write_atomically(path: &Path, bytes: &[u8]) -> Result<(), Error> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push("~");
+    let mut file = File::create(&tmp)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, path)?;
+    sync_parent(path)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<(), Error> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+// Directories can't be opened to sync them on other platforms.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Reads the file at `path`, or returns `None` if it doesn't exist.
+pub(crate) fn read_if_exists(path: &Path) -> Result<Option<Vec<u8>>, Error> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -0,0 +1,406 @@
+//! Binary In-Place Format, the encoding `ssb-db2` stores messages in, like
+//! the JS `bipf` module.
+//!
+//! Every value is a varint tag of `length << 3 | type` followed by `length`
+//! bytes. Objects and arrays hold their members' encodings one after the
+//! other, so a field can be found by skipping over the ones before it,
+//! without decoding anything:
+//!
+//! ```
+//! use flumedb::bipf;
+//! use serde_json::json;
+//!
+//! let msg = json!({"value": {"author": "@me", "content": {"type": "post"}}});
+//! let bytes = bipf::encode(&msg);
+//!
+//! let ty = bipf::seek_path(&bytes, 0, &["value", "content", "type"]).unwrap();
+//! assert_eq!(bipf::decode_at(&bytes, ty).unwrap(), json!("post"));
+//! assert_eq!(bipf::decode(&bytes).unwrap(), msg);
+//! ```
+//!
+//! The JS `bipf` keeps the fields of objects in the order they were written,
+//! but a `serde_json::Value` sorts them. [`encode_json`] and [`decode_json`]
+//! work on JSON text instead, and keep the order:
+//!
+//! ```
+//! use flumedb::bipf;
+//!
+//! let bytes = bipf::encode_json(br#"{"b": 1, "a": 2}"#).unwrap();
+//! assert_eq!(bipf::decode_json(&bytes).unwrap(), r#"{"b":1,"a":2}"#);
+//! ```
+
+use crate::flume_log::Error;
+use crate::json::{self, Json};
+use serde_json::{Number, Value};
+use thiserror::Error;
+
+const STRING: u8 = 0;
+const BUFFER: u8 = 1;
+const INT: u8 = 2;
+const DOUBLE: u8 = 3;
+const ARRAY: u8 = 4;
+const OBJECT: u8 = 5;
+const BOOLNULL: u8 = 6;
+
+const TYPE_BITS: u32 = 3;
+const TYPE_MASK: u64 = 0b111;
+
+// 2^53, past which doubles can't hold every whole number.
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.0;
+
+#[derive(Debug, Error)]
+pub enum BipfError {
+    #[error("Value at {position} runs past the end of the buffer")]
+    Truncated { position: usize },
+
+    #[error("Value at {position} has type {value_type} and length {length}, which is invalid")]
+    InvalidValue {
+        position: usize,
+        value_type: u8,
+        length: usize,
+    },
+
+    #[error("Invalid UTF-8 in string at {position}")]
+    Utf8 { position: usize },
+}
+
+/// Encodes `value` as BIPF.
+///
+/// Whole numbers that fit in an `i32` are stored as ints, and all other
+/// numbers as doubles, like the JS `bipf` does. Object fields are stored in
+/// the order `value` has them, which is sorted by key; use [`encode_json`]
+/// to keep the order of a JSON text.
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_into(value, &mut out);
+    out
+}
+
+/// Appends the BIPF encoding of `value` to `out`.
+pub fn encode_into(value: &Value, out: &mut Vec<u8>) {
+    write_value(&Json::from(value), out);
+}
+
+/// Encodes the JSON text `json` as BIPF, keeping the order of object fields,
+/// as the JS `bipf` does for the result of `JSON.parse`.
+pub fn encode_json(json: &[u8]) -> Result<Vec<u8>, Error> {
+    let value: Json = serde_json::from_slice(json)?;
+    let mut out = Vec::new();
+    write_value(&value, &mut out);
+    Ok(out)
+}
+
+fn write_value(value: &Json, out: &mut Vec<u8>) {
+    match value {
+        Json::Null => write_tag(out, BOOLNULL, 0),
+        Json::Bool(b) => {
+            write_tag(out, BOOLNULL, 1);
+            out.push(*b as u8);
+        }
+        Json::Number(n) => match n.as_i64().filter(|i| *i as i32 as i64 == *i) {
+            Some(i) => {
+                write_tag(out, INT, 4);
+                out.extend_from_slice(&(i as i32).to_le_bytes());
+            }
+            None => {
+                write_tag(out, DOUBLE, 8);
+                out.extend_from_slice(&n.as_f64().unwrap_or(0.0).to_le_bytes());
+            }
+        },
+        Json::String(s) => {
+            write_tag(out, STRING, s.len());
+            out.extend_from_slice(s.as_bytes());
+        }
+        Json::Array(items) => {
+            let mut body = Vec::new();
+            for item in items {
+                write_value(item, &mut body);
+            }
+            write_tag(out, ARRAY, body.len());
+            out.extend_from_slice(&body);
+        }
+        Json::Object(fields) => {
+            let mut body = Vec::new();
+            for (key, value) in fields {
+                write_tag(&mut body, STRING, key.len());
+                body.extend_from_slice(key.as_bytes());
+                write_value(value, &mut body);
+            }
+            write_tag(out, OBJECT, body.len());
+            out.extend_from_slice(&body);
+        }
+    }
+}
+
+/// Decodes the BIPF value at the start of `bytes`.
+pub fn decode(bytes: &[u8]) -> Result<Value, Error> {
+    decode_at(bytes, 0)
+}
+
+/// Decodes the BIPF value that starts at `position` in `bytes`, as found by
+/// [`seek_key`] or [`seek_path`].
+///
+/// Buffers decode to `{"type": "Buffer", "data": [...]}`, which is what
+/// Node turns them into in JSON, and non-string object keys decode to their
+/// JSON encoding.
+pub fn decode_at(bytes: &[u8], position: usize) -> Result<Value, Error> {
+    Ok(decode_value(bytes, position)?.into())
+}
+
+/// Decodes the BIPF value at the start of `bytes` to JSON text, with object
+/// fields in the order they're stored.
+pub fn decode_json(bytes: &[u8]) -> Result<String, Error> {
+    decode_json_at(bytes, 0)
+}
+
+/// Decodes the BIPF value that starts at `position` in `bytes` to JSON text,
+/// like [`decode_json`].
+pub fn decode_json_at(bytes: &[u8], position: usize) -> Result<String, Error> {
+    Ok(json::encode(&decode_value(bytes, position)?, false))
+}
+
+/// The position of the value of field `key` in the object at `position` in
+/// `bytes`, or `None` if there's no object there, or it has no such field.
+pub fn seek_key(bytes: &[u8], position: usize, key: &str) -> Option<usize> {
+    let (value_type, body) = read_value(bytes, position).ok()?;
+    if value_type != OBJECT {
+        return None;
+    }
+    let mut p = body.start;
+    while p < body.end {
+        let (key_type, key_body) = read_value(bytes, p).ok()?;
+        let value_position = key_body.end;
+        if key_type == STRING && &bytes[key_body] == key.as_bytes() {
+            return Some(value_position);
+        }
+        p = read_value(bytes, value_position).ok()?.1.end;
+    }
+    None
+}
+
+/// The position of the value at `path` under the object at `position` in
+/// `bytes`, following one field per element of `path`.
+pub fn seek_path(bytes: &[u8], position: usize, path: &[&str]) -> Option<usize> {
+    path.iter()
+        .try_fold(position, |position, key| seek_key(bytes, position, key))
+}
+
+fn write_tag(out: &mut Vec<u8>, value_type: u8, length: usize) {
+    let mut tag = (length as u64) << TYPE_BITS | value_type as u64;
+    loop {
+        let byte = (tag & 0x7f) as u8;
+        tag >>= 7;
+        if tag == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// The type and body of the value at `position`.
+fn read_value(bytes: &[u8], position: usize) -> Result<(u8, std::ops::Range<usize>), BipfError> {
+    let truncated = BipfError::Truncated { position };
+    let mut tag: u64 = 0;
+    let mut p = position;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(p).ok_or(BipfError::Truncated { position })?;
+        p += 1;
+        tag |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            let length = (tag >> TYPE_BITS) as usize;
+            let end = p.checked_add(length).filter(|end| *end <= bytes.len());
+            return end
+                .map(|end| ((tag & TYPE_MASK) as u8, p..end))
+                .ok_or(truncated);
+        }
+    }
+    Err(truncated)
+}
+
+fn decode_value(bytes: &[u8], position: usize) -> Result<Json, BipfError> {
+    let (value_type, body) = read_value(bytes, position)?;
+    let invalid = || BipfError::InvalidValue {
+        position,
+        value_type,
+        length: body.len(),
+    };
+    let data = &bytes[body.clone()];
+
+    let value = match (value_type, data.len()) {
+        (STRING, _) => Json::String(
+            std::str::from_utf8(data)
+                .map_err(|_| BipfError::Utf8 { position })?
+                .to_string(),
+        ),
+        (BUFFER, _) => {
+            let data = data.iter().map(|b| Json::Number((*b).into())).collect();
+            Json::Object(vec![
+                ("type".to_string(), Json::String("Buffer".to_string())),
+                ("data".to_string(), Json::Array(data)),
+            ])
+        }
+        (INT, 4) => {
+            let mut int = [0; 4];
+            int.copy_from_slice(data);
+            Json::Number(i32::from_le_bytes(int).into())
+        }
+        (DOUBLE, 8) => {
+            let mut double = [0; 8];
+            double.copy_from_slice(data);
+            let double = f64::from_le_bytes(double);
+            // JS has no separate integers, so whole doubles are whole numbers.
+            if double.fract() == 0.0 && double.abs() < MAX_SAFE_INTEGER {
+                Json::Number((double as i64).into())
+            } else {
+                // JSON has no NaN or infinities, and JSON.stringify makes them null.
+                Number::from_f64(double).map_or(Json::Null, Json::Number)
+            }
+        }
+        (ARRAY, _) => {
+            let mut items = Vec::new();
+            let mut p = body.start;
+            while p < body.end {
+                items.push(decode_value(bytes, p)?);
+                p = read_value(bytes, p)?.1.end;
+            }
+            Json::Array(items)
+        }
+        (OBJECT, _) => {
+            let mut fields = Vec::new();
+            let mut p = body.start;
+            while p < body.end {
+                let key = match decode_value(bytes, p)? {
+                    Json::String(key) => key,
+                    key => json::encode(&key, false),
+                };
+                p = read_value(bytes, p)?.1.end;
+                if p >= body.end {
+                    return Err(invalid());
+                }
+                fields.push((key, decode_value(bytes, p)?));
+                p = read_value(bytes, p)?.1.end;
+            }
+            Json::Object(fields)
+        }
+        (BOOLNULL, 0) => Json::Null,
+        (BOOLNULL, 1) => Json::Bool(data[0] != 0),
+        _ => return Err(invalid()),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bipf::*;
+    use serde_json::json;
+
+    #[test]
+    fn encodes_like_js() {
+        // Encodings from the JS bipf module.
+        assert_eq!(encode(&json!(null)), &[0x06]);
+        assert_eq!(encode(&json!(true)), &[0x0e, 0x01]);
+        assert_eq!(encode(&json!(100)), &[0x22, 0x64, 0, 0, 0]);
+        assert_eq!(encode(&json!(-1)), &[0x22, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(encode(&json!("hi")), &[0x10, b'h', b'i']);
+        assert_eq!(encode(&json!(0.5)), &[0x43, 0, 0, 0, 0, 0, 0, 0xe0, 0x3f]);
+        assert_eq!(
+            encode(&json!({"a": [1]})),
+            &[0x45, 0x08, b'a', 0x2c, 0x22, 1, 0, 0, 0]
+        );
+
+        // Tags longer than a byte are varints.
+        let long = "x".repeat(20);
+        assert_eq!(&encode(&json!(long))[..2], &[0xa0, 0x01]);
+    }
+
+    #[test]
+    fn keeps_field_order() -> Result<(), Error> {
+        // The encoding of {"b":1,"a":2} from the JS bipf module.
+        let js = [
+            0x75, 0x08, b'b', 0x22, 1, 0, 0, 0, 0x08, b'a', 0x22, 2, 0, 0, 0,
+        ];
+        assert_eq!(encode_json(br#"{"b":1,"a":2}"#)?, js);
+        assert_eq!(decode_json(&js)?, r#"{"b":1,"a":2}"#);
+        assert_eq!(seek_key(&js, 0, "a"), Some(10));
+
+        // A Value sorts its fields, but decodes the same.
+        assert_ne!(encode(&json!({"b": 1, "a": 2})), js);
+        assert_eq!(decode(&js)?, json!({"a": 2, "b": 1}));
+
+        let text = r#"{"z":[{"y":null,"x":0.5}],"m":{"c":"%","b":true},"a":[]}"#;
+        assert_eq!(decode_json(&encode_json(text.as_bytes())?)?, text);
+        let buffer = decode_json(&[0x11, 1, 2])?;
+        assert_eq!(buffer, r#"{"type":"Buffer","data":[1,2]}"#);
+        assert!(encode_json(b"{").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn round_trips() {
+        let values = vec![
+            json!(null),
+            json!(false),
+            json!(2147483647),
+            json!(-2147483648),
+            json!(1553532843013i64),
+            json!(-0.25),
+            json!(""),
+            json!("héllo"),
+            json!([]),
+            json!({}),
+            json!({
+                "key": "%abc.sha256",
+                "value": {
+                    "previous": null,
+                    "sequence": 1,
+                    "content": {"type": "post", "text": "x".repeat(300), "mentions": [1, "a", {}]},
+                },
+                "timestamp": 1553532843013.002,
+            }),
+        ];
+        for value in values {
+            let bytes = encode(&value);
+            assert_eq!(decode(&bytes).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn seeks_by_path() {
+        let msg = json!({
+            "key": "%abc.sha256",
+            "value": {"author": "@me", "content": {"type": "vote", "vote": {"value": 1}}},
+        });
+        let bytes = encode(&msg);
+
+        let vote = seek_path(&bytes, 0, &["value", "content", "vote"]).unwrap();
+        assert_eq!(decode_at(&bytes, vote).unwrap(), json!({"value": 1}));
+        let value = seek_key(&bytes, vote, "value").unwrap();
+        assert_eq!(decode_at(&bytes, value).unwrap(), json!(1));
+
+        assert_eq!(seek_path(&bytes, 0, &[]), Some(0));
+        assert_eq!(seek_path(&bytes, 0, &["value", "nope"]), None);
+        assert_eq!(seek_path(&bytes, 0, &["key", "value"]), None);
+    }
+
+    #[test]
+    fn decodes_buffers_and_rejects_bad_input() {
+        // A 2 byte buffer.
+        assert_eq!(
+            decode(&[0x11, 1, 2]).unwrap(),
+            json!({"type": "Buffer", "data": [1, 2]})
+        );
+
+        for bad in &[
+            &[][..],
+            &[0x22, 1, 0],
+            &[0x1a, 1, 0, 0],
+            &[0x16, 0, 0],
+            &[0x10, 0xff, 0xfe],
+        ] {
+            assert!(decode(bad).is_err(), "decoded {:?}", bad);
+        }
+        // An object with a key but no value.
+        assert!(decode(&[0x15, 0x08, b'a']).is_err());
+    }
+}
@@ -0,0 +1,499 @@
+use crate::atomic_file::{read_if_exists, write_atomically};
+use crate::flume_log::*;
+use crate::flume_view::*;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use crate::query::Filter;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// Bump this whenever the way entries are numbered or matched changes.
+const VERSION: u32 = 1;
+
+const WORD_BITS: usize = 64;
+
+/// A fixed-length set of bits, one per entry in the log.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bitset {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Bitset {
+    /// A bitset of `len` bits, all of them unset.
+    pub fn new(len: usize) -> Bitset {
+        Bitset {
+            words: vec![0; len.div_ceil(WORD_BITS)],
+            len,
+        }
+    }
+
+    /// A bitset of `len` bits, all of them set.
+    pub fn full(len: usize) -> Bitset {
+        Bitset::new(len).not()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the bitset has `len` bits, and the words to hold them.
+    fn has_len(&self, len: usize) -> bool {
+        self.len == len && self.words.len() == len.div_ceil(WORD_BITS)
+    }
+
+    pub fn get(&self, i: usize) -> bool {
+        i < self.len && self.words[i / WORD_BITS] & (1 << (i % WORD_BITS)) != 0
+    }
+
+    /// Sets bit `i`, which must be less than the length.
+    pub fn set(&mut self, i: usize) {
+        assert!(i < self.len, "bit {} is out of range", i);
+        self.words[i / WORD_BITS] |= 1 << (i % WORD_BITS);
+    }
+
+    /// Adds a bit to the end of the set.
+    pub fn push(&mut self, bit: bool) {
+        if self.len.is_multiple_of(WORD_BITS) {
+            self.words.push(0);
+        }
+        self.len += 1;
+        if bit {
+            self.set(self.len - 1);
+        }
+    }
+
+    /// The bits set in both `self` and `other`, up to the shorter length.
+    pub fn and(&self, other: &Bitset) -> Bitset {
+        self.combine(other, self.len.min(other.len), |a, b| a & b)
+    }
+
+    /// The bits set in either `self` or `other`, up to the longer length.
+    pub fn or(&self, other: &Bitset) -> Bitset {
+        self.combine(other, self.len.max(other.len), |a, b| a | b)
+    }
+
+    /// The bits not set in `self`.
+    pub fn not(&self) -> Bitset {
+        let mut not = Bitset {
+            words: self.words.iter().map(|w| !w).collect(),
+            len: self.len,
+        };
+        not.clear_tail();
+        not
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    /// The indexes of the set bits, in increasing order.
+    pub fn ones(&self) -> impl Iterator<Item = usize> + '_ {
+        self.words.iter().enumerate().flat_map(|(i, word)| {
+            let mut word = *word;
+            std::iter::from_fn(move || {
+                if word == 0 {
+                    return None;
+                }
+                let bit = word.trailing_zeros() as usize;
+                word &= word - 1;
+                Some(i * WORD_BITS + bit)
+            })
+        })
+    }
+
+    fn combine<F: Fn(u64, u64) -> u64>(&self, other: &Bitset, len: usize, f: F) -> Bitset {
+        let word = |words: &[u64], i| words.get(i).cloned().unwrap_or(0);
+        let mut combined = Bitset {
+            words: (0..len.div_ceil(WORD_BITS))
+                .map(|i| f(word(&self.words, i), word(&other.words, i)))
+                .collect(),
+            len,
+        };
+        combined.clear_tail();
+        combined
+    }
+
+    // Keeps the bits past the end unset, so that words can be compared and
+    // counted without masking.
+    fn clear_tail(&mut self) {
+        let used = self.len % WORD_BITS;
+        if used != 0 {
+            if let Some(last) = self.words.last_mut() {
+                *last &= (1 << used) - 1;
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedBitsets<S, B> {
+    version: u32,
+    seq: Option<Sequence>,
+    seqs: S,
+    bitsets: B,
+}
+
+/// Bitvector indexes over the JSON entries of a log, like the ones in the
+/// JS `jitdb` that `ssb-db2` queries with.
+///
+/// The index numbers the entries of the log in order, and keeps a bitset
+/// for each predicate it has been asked about, with a bit per entry
+/// saying whether the entry matches. A predicate is any [`Filter`] other
+/// than `All`, `Any` and `Not`, which are answered by combining bitsets
+/// with AND, OR and NOT instead. Bitsets are built the first time a query
+/// uses their predicate, and kept up to date as entries are appended.
+///
+/// ```
+/// use flumedb::query::Filter;
+/// use flumedb::{BitvectorIndex, FlumeLog, MemLog};
+/// use serde_json::json;
+///
+/// let mut log = MemLog::new();
+/// let post_by_a = log.append(br#"{"type": "post", "author": "a"}"#).unwrap();
+/// log.append(br#"{"type": "post", "author": "b"}"#).unwrap();
+/// log.append(br#"{"type": "vote", "author": "a"}"#).unwrap();
+///
+/// let mut index = BitvectorIndex::new();
+/// let follows = Filter::Any(vec![
+///     Filter::eq(&["author"], json!("a")),
+///     Filter::eq(&["author"], json!("c")),
+/// ]);
+/// let query = Filter::All(vec![Filter::eq(&["type"], json!("post")), follows]);
+/// assert_eq!(index.query(&log, &query), &[post_by_a]);
+/// ```
+pub struct BitvectorIndex {
+    seqs: Vec<Sequence>,
+    since: Option<Sequence>,
+    bitsets: BTreeMap<String, (Filter, Bitset)>,
+    path: Option<PathBuf>,
+}
+
+impl BitvectorIndex {
+    /// Creates an index that's kept in memory only.
+    pub fn new() -> BitvectorIndex {
+        BitvectorIndex {
+            seqs: Vec::new(),
+            since: None,
+            bitsets: BTreeMap::new(),
+            path: None,
+        }
+    }
+
+    /// Creates an index that's saved to the file at `path`, starting from
+    /// the bitsets saved there if there are any.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<BitvectorIndex, Error> {
+        let mut index = BitvectorIndex::new();
+        index.path = Some(path.as_ref().to_owned());
+
+        if let Some(bytes) = read_if_exists(path.as_ref())? {
+            let saved: SavedBitsets<Vec<Sequence>, Vec<(Filter, Bitset)>> =
+                serde_cbor::from_slice(&bytes)?;
+            // Bitsets that don't have a bit per entry, or the words to hold
+            // them, would read and write out of range, so saved state with
+            // any of them is discarded like an old version is.
+            let valid = saved
+                .bitsets
+                .iter()
+                .all(|(_, bitset)| bitset.has_len(saved.seqs.len()));
+            if saved.version == VERSION && valid {
+                index.seqs = saved.seqs;
+                index.since = saved.seq;
+                for (filter, bitset) in saved.bitsets {
+                    index
+                        .bitsets
+                        .insert(predicate_key(&filter), (filter, bitset));
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    /// Writes the index to its file. Does nothing for in-memory indexes.
+    pub fn save(&self) -> Result<(), Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let saved = SavedBitsets {
+            version: VERSION,
+            seq: self.since,
+            seqs: &self.seqs,
+            bitsets: self.bitsets.values().collect::<Vec<_>>(),
+        };
+        write_atomically(path, &serde_cbor::to_vec(&saved)?)
+    }
+
+    /// Adds the entries in `log` that haven't been indexed yet to every
+    /// bitset.
+    pub fn catch_up<L, I>(&mut self, log: &L)
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        let since = self.since;
+        catch_up(self, log, since);
+    }
+
+    /// The sequences of the entries in `log` that match `filter`, in log
+    /// order. Catches the index up with `log` first, and builds the bitsets
+    /// for any predicates in `filter` that don't have one yet.
+    pub fn query<L, I>(&mut self, log: &L, filter: &Filter) -> Vec<Sequence>
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        self.bitset(log, filter)
+            .ones()
+            .map(|i| self.seqs[i])
+            .collect()
+    }
+
+    /// The number of entries in `log` that match `filter`.
+    pub fn count<L, I>(&mut self, log: &L, filter: &Filter) -> usize
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        self.bitset(log, filter).count_ones()
+    }
+
+    /// The bitset of the entries in `log` that match `filter`, with a bit
+    /// per entry numbered in log order.
+    pub fn bitset<L, I>(&mut self, log: &L, filter: &Filter) -> Bitset
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        self.catch_up(log);
+        self.combine(log, filter)
+    }
+
+    /// Whether the index has a bitset for the predicate `filter`.
+    pub fn has_bitset(&self, filter: &Filter) -> bool {
+        self.bitsets.contains_key(&predicate_key(filter))
+    }
+
+    /// The number of entries indexed.
+    pub fn len(&self) -> usize {
+        self.seqs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seqs.is_empty()
+    }
+
+    /// The sequence of the last entry the index has seen, if any.
+    pub fn since(&self) -> Option<Sequence> {
+        self.since
+    }
+
+    fn combine<L, I>(&mut self, log: &L, filter: &Filter) -> Bitset
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        let len = self.seqs.len();
+        match filter {
+            Filter::All(filters) => filters
+                .iter()
+                .fold(Bitset::full(len), |acc, f| acc.and(&self.combine(log, f))),
+            Filter::Any(filters) => filters
+                .iter()
+                .fold(Bitset::new(len), |acc, f| acc.or(&self.combine(log, f))),
+            Filter::Not(filter) => self.combine(log, filter).not(),
+            predicate => self.predicate_bitset(log, predicate).clone(),
+        }
+    }
+
+    /// The bitset for `predicate`, built by scanning the indexed part of
+    /// `log` if there isn't one yet.
+    fn predicate_bitset<L, I>(&mut self, log: &L, predicate: &Filter) -> &Bitset
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        let key = predicate_key(predicate);
+        if !self.bitsets.contains_key(&key) {
+            let mut bitset = Bitset::new(self.seqs.len());
+            if let Some(since) = self.since {
+                for entry in log.iter_at_offset(0).take_while(|e| e.offset <= since) {
+                    if let Ok(i) = self.seqs.binary_search(&entry.offset) {
+                        if predicate.matches(&parse(&entry.data)) {
+                            bitset.set(i);
+                        }
+                    }
+                }
+            }
+            self.bitsets
+                .insert(key.clone(), (predicate.clone(), bitset));
+        }
+        &self.bitsets[&key].1
+    }
+}
+
+impl Default for BitvectorIndex {
+    fn default() -> BitvectorIndex {
+        BitvectorIndex::new()
+    }
+}
+
+impl FlumeView for BitvectorIndex {
+    fn append(&mut self, seq: Sequence, item: &[u8]) {
+        if self.since.is_some_and(|since| seq <= since) {
+            return;
+        }
+        let value = parse(item);
+        for (filter, bitset) in self.bitsets.values_mut() {
+            bitset.push(filter.matches(&value));
+        }
+        self.seqs.push(seq);
+        self.since = Some(seq);
+    }
+
+    fn latest(&self) -> Sequence {
+        self.since.unwrap_or(0)
+    }
+}
+
+fn predicate_key(filter: &Filter) -> String {
+    // Filters hold JSON values, so they always serialize.
+    serde_json::to_string(filter).unwrap()
+}
+
+/// The JSON value of an entry, or `null` if it isn't JSON.
+fn parse(data: &[u8]) -> Value {
+    serde_json::from_slice(data).unwrap_or(Value::Null)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bitvector_index::*;
+    use crate::mem_log::MemLog;
+    use crate::offset_log::OffsetLog;
+    use serde_json::json;
+    use tempfile::{tempdir, tempfile};
+
+    fn append<L: FlumeLog>(log: &mut L, ty: &str, author: &str) -> Sequence {
+        let entry = json!({"type": ty, "author": author});
+        log.append(&serde_json::to_vec(&entry).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn bitset_ops() {
+        let mut a = Bitset::new(70);
+        a.set(0);
+        a.set(65);
+        let mut b = Bitset::default();
+        for i in 0..100 {
+            b.push(i % 5 == 0);
+        }
+
+        assert_eq!(a.and(&b).ones().collect::<Vec<_>>(), &[0, 65]);
+        assert_eq!(a.and(&b).len(), 70);
+        assert_eq!(a.or(&b).len(), 100);
+        assert_eq!(a.or(&b).count_ones(), 20);
+        assert_eq!(a.not().count_ones(), 68);
+        assert!(!a.not().get(65) && a.not().get(69) && !a.not().get(70));
+        assert_eq!(Bitset::full(3).ones().collect::<Vec<_>>(), &[0, 1, 2]);
+        assert!(Bitset::new(0).is_empty());
+    }
+
+    #[test]
+    fn combines_predicates() {
+        let mut log = MemLog::new();
+        let p1 = append(&mut log, "post", "a");
+        let v1 = append(&mut log, "vote", "a");
+        let p2 = append(&mut log, "post", "b");
+        log.append(b"not json").unwrap();
+        let p3 = append(&mut log, "post", "c");
+
+        let post = Filter::eq(&["type"], json!("post"));
+        let by_a = Filter::eq(&["author"], json!("a"));
+        let by_b = Filter::eq(&["author"], json!("b"));
+
+        let mut index = BitvectorIndex::new();
+        assert!(!index.has_bitset(&post));
+        assert_eq!(index.query(&log, &post), &[p1, p2, p3]);
+        assert!(index.has_bitset(&post));
+        assert!(!index.has_bitset(&by_a));
+
+        let follows = Filter::Any(vec![by_a.clone(), by_b.clone()]);
+        let query = Filter::All(vec![post.clone(), follows.clone()]);
+        assert_eq!(index.query(&log, &query), &[p1, p2]);
+        assert_eq!(index.query(&log, &follows), &[p1, v1, p2]);
+        assert_eq!(
+            index.query(
+                &log,
+                &Filter::All(vec![post.clone(), Filter::Not(Box::new(by_a))])
+            ),
+            &[p2, p3]
+        );
+        assert_eq!(index.count(&log, &Filter::Not(Box::new(post))), 2);
+        assert_eq!(index.count(&log, &Filter::All(vec![])), 5);
+        assert_eq!(index.count(&log, &Filter::Any(vec![])), 0);
+    }
+
+    #[test]
+    fn updates_incrementally_and_persists() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("bitsets.cbor");
+        let mut log = OffsetLog::<u32>::from_file(tempfile()?)?;
+        let post = Filter::eq(&["type"], json!("post"));
+        let by_a = Filter::eq(&["author"], json!("a"));
+
+        let p1 = append(&mut log, "post", "a");
+        let mut index = BitvectorIndex::open(&path)?;
+        assert_eq!(index.query(&log, &post), &[p1]);
+
+        // Appends update the existing bitsets without rebuilding them.
+        let p2 = append(&mut log, "post", "b");
+        index.catch_up(&log);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.query(&log, &post), &[p1, p2]);
+        index.save()?;
+
+        let p3 = append(&mut log, "post", "a");
+        let mut index = BitvectorIndex::open(&path)?;
+        assert!(index.has_bitset(&post));
+        assert_eq!(index.since(), Some(p2));
+        let query = Filter::All(vec![post, by_a]);
+        assert_eq!(index.query(&log, &query), &[p1, p3]);
+        Ok(())
+    }
+
+    #[test]
+    fn discards_invalid_bitsets() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("bitsets.cbor");
+        let mut log = MemLog::new();
+        let p1 = append(&mut log, "post", "a");
+        let p2 = append(&mut log, "post", "b");
+        let post = Filter::eq(&["type"], json!("post"));
+
+        let short_words = Bitset {
+            words: Vec::new(),
+            len: 2,
+        };
+        for bitset in [Bitset::full(3), Bitset::full(1), short_words] {
+            let saved = SavedBitsets {
+                version: VERSION,
+                seq: Some(p2),
+                seqs: vec![p1, p2],
+                bitsets: vec![(post.clone(), bitset)],
+            };
+            std::fs::write(&path, serde_cbor::to_vec(&saved)?)?;
+
+            let mut index = BitvectorIndex::open(&path)?;
+            assert!(!index.has_bitset(&post));
+            assert_eq!(index.since(), None);
+            assert_eq!(index.query(&log, &post), &[p1, p2]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+//! Codecs convert between typed values and the raw bytes stored in a log,
+//! like the JS `flumecodec` module.
+
+use crate::bipf;
+use crate::flume_log::Error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+pub trait Codec {
+    type Item;
+
+    fn encode(&self, item: &Self::Item) -> Result<Vec<u8>, Error>;
+    fn decode(&self, bytes: &[u8]) -> Result<Self::Item, Error>;
+}
+
+/// Stores values as JSON, like `flumecodec.json`.
+pub struct JsonCodec<T> {
+    item_type: PhantomData<fn() -> T>,
+}
+
+impl<T> JsonCodec<T> {
+    pub fn new() -> JsonCodec<T> {
+        JsonCodec {
+            item_type: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for JsonCodec<T> {
+    fn default() -> JsonCodec<T> {
+        JsonCodec::new()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Codec for JsonCodec<T> {
+    type Item = T;
+
+    fn encode(&self, item: &T) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(item)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, Error> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Stores values as CBOR.
+pub struct CborCodec<T> {
+    item_type: PhantomData<fn() -> T>,
+}
+
+impl<T> CborCodec<T> {
+    pub fn new() -> CborCodec<T> {
+        CborCodec {
+            item_type: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for CborCodec<T> {
+    fn default() -> CborCodec<T> {
+        CborCodec::new()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Codec for CborCodec<T> {
+    type Item = T;
+
+    fn encode(&self, item: &T) -> Result<Vec<u8>, Error> {
+        Ok(serde_cbor::to_vec(item)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, Error> {
+        Ok(serde_cbor::from_slice(bytes)?)
+    }
+}
+
+/// Stores values as BIPF, like `ssb-db2`.
+pub struct BipfCodec<T> {
+    item_type: PhantomData<fn() -> T>,
+}
+
+impl<T> BipfCodec<T> {
+    pub fn new() -> BipfCodec<T> {
+        BipfCodec {
+            item_type: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for BipfCodec<T> {
+    fn default() -> BipfCodec<T> {
+        BipfCodec::new()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Codec for BipfCodec<T> {
+    type Item = T;
+
+    fn encode(&self, item: &T) -> Result<Vec<u8>, Error> {
+        bipf::encode_json(&serde_json::to_vec(item)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, Error> {
+        Ok(serde_json::from_str(&bipf::decode_json(bytes)?)?)
+    }
+}
+
+/// Stores bytes as they are.
+#[derive(Default)]
+pub struct RawCodec;
+
+impl Codec for RawCodec {
+    type Item = Vec<u8>;
+
+    fn encode(&self, item: &Vec<u8>) -> Result<Vec<u8>, Error> {
+        Ok(item.clone())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        Ok(bytes.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::codec::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn json_round_trip() {
+        let codec = JsonCodec::<Value>::new();
+        let v = json!({"value": 1});
+        let bytes = codec.encode(&v).unwrap();
+        assert_eq!(bytes, b"{\"value\":1}");
+        assert_eq!(codec.decode(&bytes).unwrap(), v);
+    }
+
+    #[test]
+    fn cbor_round_trip() {
+        let codec = CborCodec::<(u64, String)>::new();
+        let v = (5, "five".to_string());
+        let bytes = codec.encode(&v).unwrap();
+        assert_eq!(codec.decode(&bytes).unwrap(), v);
+    }
+
+    #[test]
+    fn bipf_round_trip() {
+        let codec = BipfCodec::<Value>::new();
+        let v = json!({"value": 1});
+        let bytes = codec.encode(&v).unwrap();
+        assert_eq!(bytes, b"\x5d\x28value\x22\x01\0\0\0");
+        assert_eq!(codec.decode(&bytes).unwrap(), v);
+
+        // Struct fields keep their declared order, as a JS object's would.
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Msg {
+            b: u8,
+            a: u8,
+        }
+        let codec = BipfCodec::<Msg>::new();
+        let bytes = codec.encode(&Msg { b: 1, a: 2 }).unwrap();
+        assert_eq!(&bytes[1..3], b"\x08b");
+        assert_eq!(codec.decode(&bytes).unwrap(), Msg { b: 1, a: 2 });
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert!(JsonCodec::<Value>::new().decode(b"{").is_err());
+        assert!(CborCodec::<u64>::new().decode(b"").is_err());
+        assert!(BipfCodec::<u64>::new().decode(b"\x10").is_err());
+    }
+}
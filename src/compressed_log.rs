@@ -0,0 +1,497 @@
+use crate::flume_log::*;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use std::sync::Arc;
+use thiserror::Error;
+
+// Each stored entry is [method: u8, logical size: u32 BE, payload].
+const HEADER_SIZE: usize = 5;
+
+const STORED: u8 = 0;
+const LZ4: u8 = 1;
+const ZSTD: u8 = 2;
+const ZSTD_DICTIONARY: u8 = 3;
+// A cleared entry, whose payload is zeroes. It keeps the logical size, so
+// that it reads back as that many zeroes.
+const CLEARED: u8 = 4;
+
+/// The default largest entry a [`CompressedLog`] will write or read.
+pub const DEFAULT_MAX_ENTRY_SIZE: usize = 64 * 1024 * 1024;
+
+#[derive(Debug, Error)]
+pub enum CompressedLogError {
+    #[error("Unknown compression method {method}")]
+    UnknownMethod { method: u8 },
+
+    #[error("Entry was compressed with a dictionary, but the log has none")]
+    MissingDictionary {},
+
+    #[error("Compressed entry is corrupt")]
+    CorruptEntry {},
+
+    #[error("Entry of {size} bytes is too large to compress")]
+    EntryTooLarge { size: usize },
+
+    #[error("zstd error: {0}")]
+    Zstd(#[from] std::io::Error),
+}
+
+/// How a [`CompressedLog`] compresses new entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    /// Entries are stored as they are.
+    None,
+    /// LZ4, which is fast to compress and decompress.
+    Lz4,
+    /// Zstandard at the given level (1 to 22), which compresses better.
+    /// Uses the log's dictionary, if it has one.
+    Zstd { level: i32 },
+}
+
+/// The size of the entries in a log, before and after compression.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LogSizes {
+    /// The number of entries.
+    pub entries: u64,
+    /// The total size of the entries, uncompressed.
+    pub logical: u64,
+    /// The total size of the entries as stored in the underlying log, not
+    /// counting the log's own framing.
+    pub physical: u64,
+}
+
+/// A log that compresses each entry before storing it in another log.
+///
+/// Every entry records how it was compressed, so a log can be read with any
+/// [`Compression`] setting, and changing the setting only affects new
+/// entries. Entries that don't get any smaller are stored uncompressed.
+///
+/// Small entries like SSB messages compress much better with a zstd
+/// dictionary trained on typical entries (see [`train_dictionary`]); the
+/// same dictionary is then needed to read them.
+///
+/// Clearing an entry overwrites it in the underlying log with a record of
+/// its original length, so it reads back as that many zeroes.
+///
+/// ```
+/// use flumedb::{CompressedLog, Compression, FlumeLog, MemLog};
+///
+/// let mut log = CompressedLog::new(MemLog::new(), Compression::Zstd { level: 3 });
+/// let entry = br#"{"value": "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"}"#;
+/// let seq = log.append(entry).unwrap();
+/// assert_eq!(log.get(seq).unwrap(), &entry[..]);
+///
+/// let sizes = log.sizes();
+/// assert_eq!(sizes.logical, entry.len() as u64);
+/// assert!(sizes.physical < sizes.logical);
+/// ```
+pub struct CompressedLog<L> {
+    log: L,
+    codec: Arc<EntryCodec>,
+}
+
+#[derive(Clone)]
+struct EntryCodec {
+    compression: Compression,
+    dictionary: Option<Vec<u8>>,
+    max_entry_size: usize,
+}
+
+impl<L> CompressedLog<L> {
+    pub fn new(log: L, compression: Compression) -> CompressedLog<L> {
+        CompressedLog {
+            log,
+            codec: Arc::new(EntryCodec {
+                compression,
+                dictionary: None,
+                max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+            }),
+        }
+    }
+
+    /// Compresses new zstd entries with `dictionary`, and reads entries
+    /// that were compressed with it.
+    pub fn with_dictionary(
+        log: L,
+        compression: Compression,
+        dictionary: Vec<u8>,
+    ) -> CompressedLog<L> {
+        CompressedLog {
+            log,
+            codec: Arc::new(EntryCodec {
+                compression,
+                dictionary: Some(dictionary),
+                max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+            }),
+        }
+    }
+
+    /// Limits entries to `max` bytes, uncompressed. Longer entries can't be
+    /// appended, and stored entries that claim to be longer are treated as
+    /// corrupt rather than allocating room for them.
+    pub fn with_max_entry_size(mut self, max: usize) -> CompressedLog<L> {
+        Arc::make_mut(&mut self.codec).max_entry_size = max;
+        self
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.codec.compression
+    }
+
+    pub fn log(&self) -> &L {
+        &self.log
+    }
+
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+
+    /// The logical and physical sizes of the entries in the log. Reads every
+    /// entry, but doesn't decompress any.
+    pub fn sizes<I>(&self) -> LogSizes
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        self.log
+            .iter_at_offset(0)
+            .fold(LogSizes::default(), |sizes, entry| LogSizes {
+                entries: sizes.entries + 1,
+                logical: sizes.logical + logical_size(&entry.data) as u64,
+                physical: sizes.physical + entry.data.len() as u64,
+            })
+    }
+}
+
+impl<L: FlumeLog + Overwrite> FlumeLog for CompressedLog<L> {
+    fn get(&self, seq: Sequence) -> Result<Vec<u8>, Error> {
+        let stored = self.log.get(seq)?;
+        self.codec
+            .decompress(&stored)
+            .map_err(|source| Error::CompressedLog {
+                sequence: seq,
+                source,
+            })
+    }
+
+    /// Overwrites the stored entry with a cleared one of the same length,
+    /// which reads back as zeroes.
+    fn clear(&mut self, seq: Sequence) -> Result<(), Error> {
+        let stored = self.log.get(seq)?;
+        if stored.len() < HEADER_SIZE {
+            return Err(Error::CompressedLog {
+                sequence: seq,
+                source: CompressedLogError::CorruptEntry {},
+            });
+        }
+        let mut cleared = vec![0; stored.len()];
+        cleared[0] = CLEARED;
+        cleared[1..HEADER_SIZE].copy_from_slice(&stored[1..HEADER_SIZE]);
+        self.log.overwrite(seq, &cleared)
+    }
+
+    fn latest(&self) -> Option<Sequence> {
+        self.log.latest()
+    }
+
+    fn get_many(&self, seqs: &[Sequence]) -> Vec<Result<Vec<u8>, Error>> {
+        let stored = self.log.get_many(seqs);
+        seqs.iter()
+            .zip(stored)
+            .map(|(seq, stored)| {
+                self.codec
+                    .decompress(&stored?)
+                    .map_err(|source| Error::CompressedLog {
+                        sequence: *seq,
+                        source,
+                    })
+            })
+            .collect()
+    }
+
+    fn append(&mut self, buff: &[u8]) -> Result<Sequence, Error> {
+        let stored = self.codec.compress(buff).map_err(Error::CompressEntry)?;
+        self.log.append(&stored)
+    }
+}
+
+impl<L, I> IterAtOffset<CompressedLogIter<I>> for CompressedLog<L>
+where
+    L: IterAtOffset<I>,
+    I: Iterator<Item = LogEntry>,
+{
+    fn iter_at_offset(&self, offset: u64) -> CompressedLogIter<I> {
+        CompressedLogIter {
+            iter: self.log.iter_at_offset(offset),
+            codec: self.codec.clone(),
+        }
+    }
+}
+
+/// Iterates over the decompressed entries of a [`CompressedLog`], stopping
+/// at the first entry that can't be decompressed.
+pub struct CompressedLogIter<I> {
+    iter: I,
+    codec: Arc<EntryCodec>,
+}
+
+impl<I: Iterator<Item = LogEntry>> Iterator for CompressedLogIter<I> {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<LogEntry> {
+        let entry = self.iter.next()?;
+        let data = self.codec.decompress(&entry.data).ok()?;
+        Some(LogEntry {
+            offset: entry.offset,
+            data,
+        })
+    }
+}
+
+/// Trains a zstd dictionary of at most `max_size` bytes on `samples`, which
+/// should be a few thousand typical entries.
+pub fn train_dictionary<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> Result<Vec<u8>, Error> {
+    Ok(zstd::dict::from_samples(samples, max_size)?)
+}
+
+impl EntryCodec {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CompressedLogError> {
+        if data.len() > self.max_entry_size.min(u32::MAX as usize) {
+            return Err(CompressedLogError::EntryTooLarge { size: data.len() });
+        }
+        let (method, payload) = match (self.compression, &self.dictionary) {
+            (Compression::None, _) => (STORED, None),
+            (Compression::Lz4, _) => (LZ4, Some(lz4_flex::block::compress(data))),
+            (Compression::Zstd { level }, None) => (ZSTD, Some(zstd::bulk::compress(data, level)?)),
+            (Compression::Zstd { level }, Some(dictionary)) => {
+                let mut compressor = zstd::bulk::Compressor::with_dictionary(level, dictionary)?;
+                (ZSTD_DICTIONARY, Some(compressor.compress(data)?))
+            }
+        };
+        let (method, payload) = match payload {
+            Some(payload) if payload.len() < data.len() => (method, payload),
+            _ => (STORED, data.to_vec()),
+        };
+
+        let mut stored = Vec::with_capacity(HEADER_SIZE + payload.len());
+        stored.push(method);
+        stored.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        stored.extend_from_slice(&payload);
+        Ok(stored)
+    }
+
+    fn decompress(&self, stored: &[u8]) -> Result<Vec<u8>, CompressedLogError> {
+        if stored.len() < HEADER_SIZE {
+            return Err(CompressedLogError::CorruptEntry {});
+        }
+        let size = logical_size(stored);
+        let payload = &stored[HEADER_SIZE..];
+        if size > self.max_entry_size || (stored[0] == STORED && size != payload.len()) {
+            return Err(CompressedLogError::CorruptEntry {});
+        }
+
+        let data = match stored[0] {
+            STORED => payload.to_vec(),
+            CLEARED => vec![0; size],
+            LZ4 => lz4_flex::block::decompress(payload, size)
+                .map_err(|_| CompressedLogError::CorruptEntry {})?,
+            ZSTD => zstd::bulk::decompress(payload, size)?,
+            ZSTD_DICTIONARY => {
+                let dictionary = self
+                    .dictionary
+                    .as_ref()
+                    .ok_or(CompressedLogError::MissingDictionary {})?;
+                zstd::bulk::Decompressor::with_dictionary(dictionary)?.decompress(payload, size)?
+            }
+            method => return Err(CompressedLogError::UnknownMethod { method }),
+        };
+        if data.len() != size {
+            return Err(CompressedLogError::CorruptEntry {});
+        }
+        Ok(data)
+    }
+}
+
+/// The uncompressed size of the stored entry `stored`.
+fn logical_size(stored: &[u8]) -> usize {
+    match stored.get(1..HEADER_SIZE) {
+        Some(size) => u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize,
+        None => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compressed_log::*;
+    use crate::conformance;
+    use crate::mem_log::MemLog;
+    use crate::offset_log::OffsetLog;
+    use serde_json::json;
+    use tempfile::tempfile;
+
+    fn messages(n: usize) -> Vec<Vec<u8>> {
+        (0..n)
+            .map(|i| {
+                let msg = json!({
+                    "key": format!("%{:044}.sha256", i),
+                    "value": {
+                        "previous": format!("%{:044}.sha256", i.saturating_sub(1)),
+                        "author": "@FCX/tsDLpubCPKKfIrw4gc+SQkHcaD17s7GI6i/ziWY=.ed25519",
+                        "sequence": i + 1,
+                        "timestamp": 1_553_532_843_013u64 + i as u64,
+                        "hash": "sha256",
+                        "content": {"type": "post", "text": format!("post number {}", i)},
+                    },
+                    "timestamp": 1_553_532_843_100u64 + i as u64,
+                });
+                serde_json::to_vec(&msg).unwrap()
+            })
+            .collect()
+    }
+
+    fn temp_log(compression: Compression) -> CompressedLog<OffsetLog<u32>> {
+        CompressedLog::new(
+            OffsetLog::from_file(tempfile().unwrap()).unwrap(),
+            compression,
+        )
+    }
+
+    #[test]
+    fn conformance() {
+        for compression in &[
+            Compression::None,
+            Compression::Lz4,
+            Compression::Zstd { level: 3 },
+        ] {
+            conformance::check_flume_log(|| temp_log(*compression));
+            conformance::check_iter_at_offset(|| temp_log(*compression));
+        }
+    }
+
+    #[test]
+    fn compresses_and_reports_sizes() -> Result<(), Error> {
+        let msgs = messages(100);
+        let logical: usize = msgs.iter().map(Vec::len).sum();
+
+        for compression in &[
+            Compression::None,
+            Compression::Lz4,
+            Compression::Zstd { level: 3 },
+        ] {
+            let mut log = temp_log(*compression);
+            let seqs: Vec<Sequence> = msgs.iter().map(|m| log.append(m).unwrap()).collect();
+            assert_eq!(log.get(seqs[42])?, msgs[42]);
+            let read: Vec<Vec<u8>> = log.iter_at_offset(0).map(|e| e.data).collect();
+            assert_eq!(read, msgs);
+
+            let sizes = log.sizes();
+            assert_eq!(sizes.entries, 100);
+            assert_eq!(sizes.logical, logical as u64);
+            if *compression == Compression::None {
+                assert_eq!(sizes.physical, (logical + 100 * HEADER_SIZE) as u64);
+            } else {
+                assert!(sizes.physical < sizes.logical, "{:?}", sizes);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn dictionaries() -> Result<(), Error> {
+        let msgs = messages(1000);
+        let dictionary = train_dictionary(&msgs, 4096)?;
+        let zstd = Compression::Zstd { level: 3 };
+
+        let mut plain = CompressedLog::new(MemLog::new(), zstd);
+        let mut with_dictionary = CompressedLog::with_dictionary(MemLog::new(), zstd, dictionary);
+        for msg in &msgs[..100] {
+            plain.append(msg)?;
+            with_dictionary.append(msg)?;
+        }
+        assert!(with_dictionary.sizes().physical < plain.sizes().physical);
+        assert_eq!(with_dictionary.get(5)?, msgs[5]);
+
+        // Without the dictionary, the entries can't be read.
+        let without = CompressedLog::new(with_dictionary.into_inner(), zstd);
+        assert!(matches!(
+            without.get(5),
+            Err(Error::CompressedLog {
+                sequence: 5,
+                source: CompressedLogError::MissingDictionary {},
+            })
+        ));
+        assert_eq!(without.iter_at_offset(0).count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn reads_entries_written_with_other_settings() -> Result<(), Error> {
+        let msgs = messages(3);
+        let mut log = CompressedLog::new(MemLog::new(), Compression::Lz4);
+        log.append(&msgs[0])?;
+        let mut log = CompressedLog::new(log.into_inner(), Compression::Zstd { level: 1 });
+        log.append(&msgs[1])?;
+        let mut log = CompressedLog::new(log.into_inner(), Compression::None);
+        log.append(&msgs[2])?;
+
+        let read: Vec<Vec<u8>> = log.iter_at_offset(0).map(|e| e.data).collect();
+        assert_eq!(read, msgs);
+
+        let mut inner = log.into_inner();
+        inner.append(&[9, 0, 0, 0, 1, 0])?;
+        let log = CompressedLog::new(inner, Compression::None);
+        assert!(matches!(
+            log.get(3),
+            Err(Error::CompressedLog {
+                source: CompressedLogError::UnknownMethod { method: 9 },
+                ..
+            })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn clears_entries() -> Result<(), Error> {
+        let msgs = messages(2);
+        let mut log = temp_log(Compression::Lz4);
+        let a = log.append(&msgs[0])?;
+        let b = log.append(&msgs[1])?;
+        let stored = log.log().get(a)?;
+        log.clear(a)?;
+        assert_eq!(log.get(a)?, vec![0; msgs[0].len()]);
+        assert_eq!(log.get(b)?, msgs[1]);
+        assert_eq!(log.log().get(a)?.len(), stored.len());
+        assert!(!log.log().get(a)?.windows(4).any(|w| w == b"post"));
+        assert_eq!(log.sizes().logical, (msgs[0].len() + msgs[1].len()) as u64);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_oversized_entries() -> Result<(), Error> {
+        let mut log = CompressedLog::new(MemLog::new(), Compression::Zstd { level: 1 })
+            .with_max_entry_size(1000);
+        log.append(&[0; 1000])?;
+        assert!(matches!(
+            log.append(&[0; 1001]),
+            Err(Error::CompressEntry(CompressedLogError::EntryTooLarge {
+                size: 1001
+            }))
+        ));
+
+        // A corrupt header can't make a read allocate gigabytes.
+        let mut inner = log.into_inner();
+        let mut header = vec![ZSTD];
+        header.extend_from_slice(&u32::MAX.to_be_bytes());
+        header.extend_from_slice(&zstd::bulk::compress(b"abc", 1)?);
+        let seq = inner.append(&header)?;
+        let log = CompressedLog::new(inner, Compression::None);
+        assert!(matches!(
+            log.get(seq),
+            Err(Error::CompressedLog {
+                source: CompressedLogError::CorruptEntry {},
+                ..
+            })
+        ));
+        Ok(())
+    }
+}
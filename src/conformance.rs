@@ -0,0 +1,314 @@
+//! A conformance test suite for [`FlumeLog`] implementations.
+//!
+//! Each check takes a function that creates a new, empty log, and panics
+//! with a description of the broken contract if the log misbehaves. Call
+//! them from your own tests to check a custom backend:
+//!
+//! ```
+//! use flumedb::conformance;
+//! use flumedb::MemLog;
+//!
+//! conformance::check_flume_log(MemLog::new);
+//! conformance::check_iter_at_offset(MemLog::new);
+//! ```
+//!
+//! Backends that can't append every kind of entry, or can't append at all,
+//! can still check the logs they read with [`check_existing_log`].
+
+use crate::flume_log::*;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use std::fmt::Debug;
+
+const ENTRIES: &[&[u8]] = &[b"{\"value\": 0}", b"", b"abc", b"{\"value\": 1}"];
+
+/// Runs every check that only needs the [`FlumeLog`] trait.
+pub fn check_flume_log<L, F>(mut new_log: F)
+where
+    L: FlumeLog,
+    F: FnMut() -> L,
+{
+    check_empty(new_log());
+    check_append_and_get(new_log());
+    check_large_entry(new_log());
+    check_missing_sequence(new_log());
+    check_clear(new_log());
+    check_get_many(new_log());
+}
+
+/// An empty log has no latest sequence, and no entry at sequence zero.
+pub fn check_empty<L: FlumeLog>(log: L) {
+    assert_eq!(log.latest(), None, "an empty log has no latest sequence");
+    assert_not_found(log.get(0), 0, "get(0) on an empty log");
+}
+
+/// Appended entries read back unchanged, sequences increase, and `latest`
+/// is the sequence of the last append.
+pub fn check_append_and_get<L: FlumeLog>(mut log: L) {
+    let seqs = append_all(&mut log);
+
+    assert!(
+        seqs.windows(2).all(|w| w[0] < w[1]),
+        "sequences must increase with each append: {:?}",
+        seqs
+    );
+    assert_eq!(log.latest(), seqs.last().cloned());
+
+    for (seq, data) in seqs.iter().zip(ENTRIES) {
+        assert_eq!(
+            &log.get(*seq).expect("get of an appended sequence failed"),
+            data,
+            "get({}) returned the wrong data",
+            seq
+        );
+    }
+}
+
+/// Entries much larger than any internal buffer read back unchanged.
+pub fn check_large_entry<L: FlumeLog>(mut log: L) {
+    let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    let seq = log.append(&data).unwrap();
+    assert_eq!(log.get(seq).unwrap(), data, "large entry was corrupted");
+}
+
+/// Reading past the end of the log, or at a sequence that falls between two
+/// entries, fails with `FlumeLogError::SequenceNotFound`.
+pub fn check_missing_sequence<L: FlumeLog>(mut log: L) {
+    let seqs = append_all(&mut log);
+
+    let latest = *seqs.last().unwrap();
+    assert_not_found(log.get(latest + 1), latest + 1, "get past the latest entry");
+    let past_end = latest + 1_000_000;
+    assert_not_found(log.get(past_end), past_end, "get past the end of the log");
+
+    for w in seqs.windows(2) {
+        for seq in (w[0] + 1)..w[1] {
+            assert_not_found(log.get(seq), seq, "get between two entries");
+        }
+    }
+}
+
+/// A cleared entry reads back as as many zeroes as it had bytes, without
+/// affecting other entries or the latest sequence.
+pub fn check_clear<L: FlumeLog>(mut log: L) {
+    let seqs = append_all(&mut log);
+    let latest = log.latest();
+
+    log.clear(seqs[0])
+        .expect("clear of an appended sequence failed");
+
+    let cleared = log.get(seqs[0]).expect("get of a cleared sequence failed");
+    assert!(
+        cleared.len() == ENTRIES[0].len() && cleared.iter().all(|b| *b == 0),
+        "cleared entry must read back as {} zeroes, got {:?}",
+        ENTRIES[0].len(),
+        cleared
+    );
+    assert_eq!(
+        log.get(seqs[2]).unwrap(),
+        ENTRIES[2],
+        "clear affected another entry"
+    );
+    assert_eq!(log.latest(), latest, "clear changed the latest sequence");
+
+    let past_end = seqs.last().unwrap() + 1_000_000;
+    assert_not_found(
+        log.clear(past_end),
+        past_end,
+        "clear past the end of the log",
+    );
+}
+
+/// Iterating from a sequence yields the entries from there to the end of
+/// the log, in order, with `LogEntry::offset` set to each entry's sequence.
+pub fn check_iter_at_offset<L, I, F>(mut new_log: F)
+where
+    L: FlumeLog + IterAtOffset<I>,
+    I: Iterator<Item = LogEntry>,
+    F: FnMut() -> L,
+{
+    let log = new_log();
+    assert!(
+        log.iter_at_offset(0).next().is_none(),
+        "iterating an empty log must yield nothing"
+    );
+
+    let mut log = new_log();
+    let seqs = append_all(&mut log);
+
+    let entries: Vec<LogEntry> = log.iter_at_offset(seqs[0]).collect();
+    let offsets: Vec<Sequence> = entries.iter().map(|e| e.offset).collect();
+    assert_eq!(offsets, seqs, "iteration must yield every entry's sequence");
+    for (entry, data) in entries.iter().zip(ENTRIES) {
+        assert_eq!(&entry.data, data, "iteration yielded the wrong data");
+    }
+
+    let offsets: Vec<Sequence> = log.iter_at_offset(seqs[2]).map(|e| e.offset).collect();
+    assert_eq!(
+        offsets,
+        &seqs[2..],
+        "iteration must start at the given sequence"
+    );
+}
+
+/// `get_many` returns what `get` would for each sequence, in the order
+/// asked for, including repeated and missing sequences.
+pub fn check_get_many<L: FlumeLog>(mut log: L) {
+    let seqs = append_all(&mut log);
+    assert!(log.get_many(&[]).is_empty(), "get_many of no sequences");
+
+    let missing = seqs.last().unwrap() + 1_000_000;
+    let results = log.get_many(&[seqs[3], seqs[0], missing, seqs[2], seqs[0]]);
+    assert_eq!(
+        results.len(),
+        5,
+        "get_many must return a result per sequence"
+    );
+
+    let mut results = results.into_iter();
+    for i in &[3, 0] {
+        assert_eq!(
+            results
+                .next()
+                .unwrap()
+                .expect("get_many of an appended sequence failed"),
+            ENTRIES[*i],
+            "get_many returned the wrong data for {}",
+            seqs[*i]
+        );
+    }
+    assert_not_found(
+        results.next().unwrap(),
+        missing,
+        "get_many past the end of the log",
+    );
+    assert_eq!(results.next().unwrap().unwrap(), ENTRIES[2]);
+    assert_eq!(
+        results.next().unwrap().unwrap(),
+        ENTRIES[0],
+        "get_many of a repeated sequence"
+    );
+}
+
+/// Checks the entries already in `log`, reading them with `get`: each entry
+/// iteration yields reads back the same, iteration can start at any of
+/// them, and reads past the last one fail with
+/// `FlumeLogError::SequenceNotFound`.
+///
+/// Entries that iteration skips, like deleted records, aren't checked.
+pub fn check_existing_log<L, I, G>(log: &L, get: G)
+where
+    L: IterAtOffset<I>,
+    I: Iterator<Item = LogEntry>,
+    G: Fn(Sequence) -> Result<Vec<u8>, Error>,
+{
+    let entries: Vec<LogEntry> = log.iter_at_offset(0).collect();
+    assert!(
+        entries.windows(2).all(|w| w[0].offset < w[1].offset),
+        "iteration must yield increasing sequences"
+    );
+
+    for entry in &entries {
+        assert_eq!(
+            get(entry.offset).expect("get of an iterated sequence failed"),
+            entry.data,
+            "get({}) doesn't match iteration",
+            entry.offset
+        );
+        let first = log.iter_at_offset(entry.offset).next();
+        assert_eq!(
+            first.as_ref().map(|e| (e.offset, &e.data)),
+            Some((entry.offset, &entry.data)),
+            "iteration must start at the given sequence"
+        );
+    }
+
+    let latest = entries.last().map_or(0, |e| e.offset);
+    if !entries.is_empty() {
+        assert_not_found(get(latest + 1), latest + 1, "get past the latest entry");
+    }
+    let past_end = latest + 1_000_000;
+    assert_not_found(get(past_end), past_end, "get past the end of the log");
+}
+
+fn assert_not_found<T: Debug>(result: Result<T, Error>, seq: Sequence, what: &str) {
+    match result {
+        Err(Error::Log(FlumeLogError::SequenceNotFound { sequence })) if sequence == seq => {}
+        r => panic!(
+            "{} must fail with SequenceNotFound {{ sequence: {} }}, got {:?}",
+            what, seq, r
+        ),
+    }
+}
+
+fn append_all<L: FlumeLog>(log: &mut L) -> Vec<Sequence> {
+    ENTRIES
+        .iter()
+        .map(|data| log.append(data).expect("append failed"))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::aligned_offset_log::AlignedOffsetLog;
+    use crate::conformance::*;
+    use crate::go_offset_log::GoOffsetLog;
+    use crate::mem_log::MemLog;
+    use crate::offset_log::OffsetLog;
+    use std::path::PathBuf;
+    use tempfile::tempfile;
+
+    fn temp_offset_log<ByteType>() -> OffsetLog<ByteType> {
+        OffsetLog::from_file(tempfile().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn mem_log() {
+        check_flume_log(MemLog::new);
+        check_iter_at_offset(MemLog::new);
+    }
+
+    #[test]
+    fn offset_log_u32() {
+        check_flume_log(temp_offset_log::<u32>);
+        check_iter_at_offset(temp_offset_log::<u32>);
+    }
+
+    #[test]
+    fn offset_log_u64() {
+        check_flume_log(temp_offset_log::<u64>);
+        check_iter_at_offset(temp_offset_log::<u64>);
+    }
+
+    // Can't hold empty or 100 KB entries, so only the checks that don't
+    // append them apply.
+    #[test]
+    fn aligned_offset_log() {
+        let new_log = || AlignedOffsetLog::from_file(tempfile().unwrap(), 256).unwrap();
+        check_empty(new_log());
+
+        let mut log = new_log();
+        for data in ENTRIES.iter().filter(|data| !data.is_empty()) {
+            log.append(data).unwrap();
+        }
+        check_existing_log(&log, |seq| log.get(seq));
+
+        let fixture = test_vec("aligned_offset/log.bipf");
+        let log = AlignedOffsetLog::open_read_only(fixture, 256).unwrap();
+        check_existing_log(&log, |seq| log.get(seq));
+    }
+
+    // Read-only, so only its fixture can be checked.
+    #[test]
+    fn go_offset_log() {
+        let log = GoOffsetLog::open_read_only(test_vec("four_ssb_messages")).unwrap();
+        check_existing_log(&log, |seq| log.get(seq));
+    }
+
+    fn test_vec(name: &str) -> PathBuf {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("test_vecs");
+        path.push(name);
+        path
+    }
+}
@@ -0,0 +1,353 @@
+use crate::flume_log::*;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use ssb_crypto::secretbox::{Hmac, Key, Nonce};
+use std::sync::Arc;
+
+// The random bytes at the start of each stored entry, which make up the
+// rest of its nonce along with its sequence.
+const SALT_SIZE: usize = 16;
+
+/// A log that encrypts each entry before storing it in another log, so that
+/// the log file on disk doesn't reveal what's in it.
+///
+/// Entries are sealed with xsalsa20-poly1305 (libsodium's `secretbox`)
+/// under the caller's key. The nonce is 16 random bytes, stored at the start
+/// of the entry, followed by the sequence the entry is stored at. Because
+/// the random part is new for every entry, nonces don't repeat even when
+/// the underlying log stores a second entry at a sequence it used before,
+/// as an `AlignedOffsetLog` does after dropping a torn write at the end of
+/// its file. Each stored entry is [`EncryptedLog::OVERHEAD`] bytes longer
+/// than the plaintext, and entry lengths and sequences aren't hidden.
+///
+/// Reading an entry that was modified, moved to another sequence or zeroed
+/// fails with [`Error::Tampered`]. Clearing an entry overwrites it with
+/// zeroes sealed under a new nonce, which read back as zeroes.
+///
+/// ```
+/// use flumedb::{EncryptedLog, FlumeLog, MemLog};
+/// use ssb_crypto::secretbox::Key;
+///
+/// let mut log = EncryptedLog::new(MemLog::new(), Key::generate());
+/// let seq = log.append(b"secret").unwrap();
+/// assert_eq!(log.get(seq).unwrap(), b"secret");
+/// assert_ne!(log.log().get(seq).unwrap(), b"secret");
+/// ```
+pub struct EncryptedLog<L> {
+    log: L,
+    cipher: Arc<Cipher>,
+}
+
+struct Cipher {
+    key: Key,
+}
+
+impl<L> EncryptedLog<L> {
+    /// How many bytes longer a stored entry is than its plaintext (32).
+    pub const OVERHEAD: usize = SALT_SIZE + Hmac::SIZE;
+
+    pub fn new(log: L, key: Key) -> EncryptedLog<L> {
+        EncryptedLog {
+            log,
+            cipher: Arc::new(Cipher { key }),
+        }
+    }
+
+    /// The underlying log, which holds the encrypted entries.
+    pub fn log(&self) -> &L {
+        &self.log
+    }
+
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+}
+
+impl<L: FlumeLog + NextSequence + Overwrite> FlumeLog for EncryptedLog<L> {
+    fn get(&self, seq: Sequence) -> Result<Vec<u8>, Error> {
+        let sealed = self.log.get(seq)?;
+        self.cipher.open(seq, &sealed)
+    }
+
+    fn get_many(&self, seqs: &[Sequence]) -> Vec<Result<Vec<u8>, Error>> {
+        let sealed = self.log.get_many(seqs);
+        seqs.iter()
+            .zip(sealed)
+            .map(|(seq, sealed)| self.cipher.open(*seq, &sealed?))
+            .collect()
+    }
+
+    /// Overwrites the entry with zeroes sealed under a new nonce, which
+    /// read back as zeroes.
+    fn clear(&mut self, seq: Sequence) -> Result<(), Error> {
+        let len = self.log.get(seq)?.len();
+        if len < Self::OVERHEAD {
+            return Err(Error::Tampered { sequence: seq });
+        }
+        let cleared = self.cipher.seal(seq, &vec![0; len - Self::OVERHEAD]);
+        self.log.overwrite(seq, &cleared)
+    }
+
+    fn latest(&self) -> Option<Sequence> {
+        self.log.latest()
+    }
+
+    /// Fails with `FlumeLogError::UnexpectedSequence` if the underlying log
+    /// stores the entry somewhere other than it said it would. The entry is
+    /// still in the underlying log, but sealed for the wrong sequence, so
+    /// reading it fails with [`Error::Tampered`], and iteration stops there.
+    fn append(&mut self, buff: &[u8]) -> Result<Sequence, Error> {
+        let seq = self.log.next_sequence(buff.len() + Self::OVERHEAD);
+        let sealed = self.cipher.seal(seq, buff);
+
+        let appended = self.log.append(&sealed)?;
+        if appended != seq {
+            return Err(FlumeLogError::UnexpectedSequence {
+                expected: seq,
+                found: appended,
+            }
+            .into());
+        }
+        Ok(appended)
+    }
+}
+
+impl<L, I> IterAtOffset<EncryptedLogIter<I>> for EncryptedLog<L>
+where
+    L: IterAtOffset<I>,
+    I: Iterator<Item = LogEntry>,
+{
+    fn iter_at_offset(&self, offset: u64) -> EncryptedLogIter<I> {
+        EncryptedLogIter {
+            iter: self.log.iter_at_offset(offset),
+            cipher: self.cipher.clone(),
+        }
+    }
+}
+
+/// Iterates over the decrypted entries of an [`EncryptedLog`], stopping at
+/// the first entry that fails authentication. `get` that entry to find out
+/// which one it was.
+pub struct EncryptedLogIter<I> {
+    iter: I,
+    cipher: Arc<Cipher>,
+}
+
+impl<I: Iterator<Item = LogEntry>> Iterator for EncryptedLogIter<I> {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<LogEntry> {
+        let entry = self.iter.next()?;
+        let data = self.cipher.open(entry.offset, &entry.data).ok()?;
+        Some(LogEntry {
+            offset: entry.offset,
+            data,
+        })
+    }
+}
+
+impl Cipher {
+    fn nonce(salt: &[u8], seq: u64) -> Nonce {
+        let mut nonce = Nonce::zero();
+        nonce.0[..SALT_SIZE].copy_from_slice(salt);
+        nonce.0[SALT_SIZE..].copy_from_slice(&seq.to_be_bytes());
+        nonce
+    }
+
+    /// Seals `data` for storing at `seq`, under a new random salt.
+    fn seal(&self, seq: u64, data: &[u8]) -> Vec<u8> {
+        let mut sealed = vec![0; SALT_SIZE + Hmac::SIZE + data.len()];
+        let (salt, sealed_data) = sealed.split_at_mut(SALT_SIZE);
+        salt.copy_from_slice(&Nonce::generate().0[..SALT_SIZE]);
+        self.key
+            .seal_attached_into(data, &Cipher::nonce(salt, seq), sealed_data);
+        sealed
+    }
+
+    /// Decrypts the entry `sealed` stored at `seq`.
+    fn open(&self, seq: Sequence, sealed: &[u8]) -> Result<Vec<u8>, Error> {
+        if sealed.len() < SALT_SIZE + Hmac::SIZE {
+            return Err(Error::Tampered { sequence: seq });
+        }
+        let (salt, sealed_data) = sealed.split_at(SALT_SIZE);
+        let mut data = vec![0; sealed_data.len() - Hmac::SIZE];
+        if self
+            .key
+            .open_attached_into(sealed_data, &Cipher::nonce(salt, seq), &mut data)
+        {
+            Ok(data)
+        } else {
+            Err(Error::Tampered { sequence: seq })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::aligned_offset_log::AlignedOffsetLog;
+    use crate::conformance;
+    use crate::encrypted_log::*;
+    use crate::mem_log::MemLog;
+    use crate::offset_log::OffsetLog;
+    use std::os::unix::fs::FileExt;
+    use tempfile::{tempdir, tempfile};
+
+    fn key() -> Key {
+        Key([7; Key::SIZE])
+    }
+
+    fn temp_log() -> EncryptedLog<OffsetLog<u32>> {
+        EncryptedLog::new(OffsetLog::from_file(tempfile().unwrap()).unwrap(), key())
+    }
+
+    #[test]
+    fn conformance() {
+        conformance::check_flume_log(temp_log);
+        conformance::check_iter_at_offset(temp_log);
+        conformance::check_flume_log(|| EncryptedLog::new(MemLog::new(), key()));
+    }
+
+    #[test]
+    fn encrypts_entries() -> Result<(), Error> {
+        let file = tempfile()?;
+        let inner = OffsetLog::<u32>::from_file(file.try_clone()?)?;
+        let mut log = EncryptedLog::new(inner, key());
+        let a = log.append(b"private metadata")?;
+        let b = log.append(b"private metadata")?;
+
+        let mut raw = vec![0; log.log().end() as usize];
+        file.read_exact_at(&mut raw, 0)?;
+        assert!(!raw.windows(7).any(|w| w == b"private"));
+        // The same plaintext encrypts differently at different sequences.
+        // An entry stored at a sequence that was used before, as when the
+        // underlying log drops a torn write, gets a different nonce.
+        assert_ne!(log.log().get(a)?, log.log().get(b)?);
+        let mut other = EncryptedLog::new(MemLog::new(), key());
+        assert_eq!(other.append(b"private metadata")?, a);
+        assert_ne!(other.log().get(a)?[..16], log.log().get(a)?[..16]);
+        assert_eq!(
+            log.log().get(a)?.len(),
+            b"private metadata".len() + EncryptedLog::<MemLog>::OVERHEAD
+        );
+
+        let entries: Vec<LogEntry> = log.iter_at_offset(0).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].offset, b);
+        assert_eq!(entries[1].data, b"private metadata");
+        Ok(())
+    }
+
+    #[test]
+    fn detects_tampering() -> Result<(), Error> {
+        let file = tempfile()?;
+        let inner = OffsetLog::<u32>::from_file(file.try_clone()?)?;
+        let mut log = EncryptedLog::new(inner, key());
+        let a = log.append(b"one")?;
+        let b = log.append(b"two")?;
+        let c = log.append(b"six")?;
+
+        // Flip a bit of the second entry's ciphertext.
+        let data_start = b + 4;
+        let mut byte = [0];
+        file.read_exact_at(&mut byte, data_start + 17)?;
+        file.write_all_at(&[byte[0] ^ 1], data_start + 17)?;
+        assert!(matches!(log.get(b), Err(Error::Tampered { sequence }) if sequence == b));
+        assert_eq!(log.get(a)?, b"one");
+        assert_eq!(log.iter_at_offset(0).count(), 1);
+
+        // Move the third entry into the first one's place.
+        let moved = log.log().get(c)?;
+        file.write_all_at(&moved, a + 4)?;
+        assert!(matches!(log.get(a), Err(Error::Tampered { .. })));
+
+        // Zero the third entry, like clearing the underlying log would.
+        file.write_all_at(&[0; 3 + EncryptedLog::<MemLog>::OVERHEAD], c + 4)?;
+        assert!(matches!(log.get(c), Err(Error::Tampered { .. })));
+
+        // A different key can't read anything.
+        let inner = log.into_inner();
+        let d = inner.end();
+        let mut log = EncryptedLog::new(inner, key());
+        log.append(b"ten")?;
+        let other = EncryptedLog::new(log.into_inner(), Key([8; Key::SIZE]));
+        assert!(matches!(other.get(d), Err(Error::Tampered { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn clears_entries() -> Result<(), Error> {
+        let mut log = EncryptedLog::new(MemLog::new(), key());
+        let a = log.append(b"one")?;
+        let b = log.append(b"two")?;
+        let sealed = log.log().get(a)?;
+
+        log.clear(a)?;
+        assert_eq!(log.get(a)?, &[0; 3]);
+        assert_eq!(log.get(b)?, b"two");
+        let cleared = log.log().get(a)?;
+        assert_eq!(cleared.len(), sealed.len());
+        assert!(cleared.iter().any(|b| *b != 0));
+
+        // A cleared entry is only valid where it was written.
+        let mut inner = log.into_inner();
+        inner.overwrite(b, &cleared)?;
+        let log = EncryptedLog::new(inner, key());
+        assert!(matches!(log.get(b), Err(Error::Tampered { .. })));
+        Ok(())
+    }
+
+    #[test]
+    fn reports_mispredicted_sequences() {
+        // Appends like a MemLog, but claims entries go one sequence later.
+        struct Liar(MemLog);
+        impl FlumeLog for Liar {
+            fn get(&self, seq: Sequence) -> Result<Vec<u8>, Error> {
+                self.0.get(seq)
+            }
+            fn clear(&mut self, seq: Sequence) -> Result<(), Error> {
+                self.0.clear(seq)
+            }
+            fn latest(&self) -> Option<Sequence> {
+                self.0.latest()
+            }
+            fn append(&mut self, buff: &[u8]) -> Result<Sequence, Error> {
+                self.0.append(buff)
+            }
+        }
+        impl NextSequence for Liar {
+            fn next_sequence(&self, len: usize) -> Sequence {
+                self.0.next_sequence(len) + 1
+            }
+        }
+        impl Overwrite for Liar {
+            fn overwrite(&mut self, seq: Sequence, data: &[u8]) -> Result<(), Error> {
+                self.0.overwrite(seq, data)
+            }
+        }
+
+        let mut log = EncryptedLog::new(Liar(MemLog::new()), key());
+        assert!(matches!(
+            log.append(b"one"),
+            Err(Error::Log(FlumeLogError::UnexpectedSequence {
+                expected: 1,
+                found: 0
+            }))
+        ));
+    }
+
+    #[test]
+    fn predicts_aligned_offset_log_sequences() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let log = AlignedOffsetLog::new(dir.path().join("log"), 128)?;
+        let mut log = EncryptedLog::new(log, key());
+        // Entries that don't fit in the rest of a block start a new one.
+        let seqs: Vec<Sequence> = (0..5).map(|i| log.append(&[i; 20]).unwrap()).collect();
+        assert_eq!(seqs, &[0, 54, 128, 182, 256]);
+        for (i, seq) in seqs.iter().enumerate() {
+            assert_eq!(log.get(*seq)?, &[i as u8; 20]);
+        }
+        log.clear(seqs[1])?;
+        assert_eq!(log.get(seqs[1])?, &[0; 20]);
+        Ok(())
+    }
+}
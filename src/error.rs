@@ -0,0 +1,76 @@
+use crate::aligned_offset_log::AlignedOffsetLogError;
+use crate::bipf::BipfError;
+use crate::compressed_log::CompressedLogError;
+use crate::flume_log::FlumeLogError;
+use crate::go_offset_log::GoFlumeOffsetLogError;
+use crate::offset_log::FlumeOffsetLogError;
+use crate::ssb_validating_log::SsbValidationError;
+use std::io;
+use std::str::Utf8Error;
+use thiserror::Error;
+
+/// The error type for every fallible operation in this crate.
+///
+/// Errors that occur while reading or decoding a log record the offset of
+/// the entry that was being read.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Log(#[from] FlumeLogError),
+
+    #[error("Offset log error at offset {offset}: {source}")]
+    OffsetLog {
+        offset: u64,
+        #[source]
+        source: FlumeOffsetLogError,
+    },
+
+    #[error("Go offset log error at offset {offset}: {source}")]
+    GoOffsetLog {
+        offset: u64,
+        #[source]
+        source: GoFlumeOffsetLogError,
+    },
+
+    #[error("Aligned offset log error at offset {offset}: {source}")]
+    AlignedOffsetLog {
+        offset: u64,
+        #[source]
+        source: AlignedOffsetLogError,
+    },
+
+    #[error("Compressed log error at sequence {sequence}: {source}")]
+    CompressedLog {
+        sequence: u64,
+        #[source]
+        source: CompressedLogError,
+    },
+
+    #[error("Unable to compress entry: {0}")]
+    CompressEntry(#[source] CompressedLogError),
+
+    #[error("Encrypted entry at sequence {sequence} failed authentication, so it was corrupted or tampered with")]
+    Tampered { sequence: u64 },
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    #[error("Invalid SSB message: {0}")]
+    SsbValidation(#[from] SsbValidationError),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("CBOR error: {0}")]
+    Cbor(#[from] serde_cbor::Error),
+
+    #[error("BIPF error: {0}")]
+    Bipf(#[from] BipfError),
+
+    #[error("Invalid UTF-8: {0}")]
+    Utf8(#[from] Utf8Error),
+
+    #[cfg(feature = "async")]
+    #[error("Blocking log task failed: {0}")]
+    BlockingTask(#[from] tokio::task::JoinError),
+}
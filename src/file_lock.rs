@@ -0,0 +1,30 @@
+use crate::flume_log::*;
+use fs2::FileExt;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+// Advisory locks are held for as long as the file (or any clone of it) is open.
+
+/// Take an exclusive lock on a log file, for a process that will write to it.
+pub(crate) fn lock_exclusive(file: &File, path: &Path) -> Result<(), Error> {
+    FileExt::try_lock_exclusive(file).map_err(|err| lock_error(err, path))
+}
+
+/// Take a shared lock on a log file, for a process that will only read it.
+pub(crate) fn lock_shared(file: &File, path: &Path) -> Result<(), Error> {
+    FileExt::try_lock_shared(file).map_err(|err| lock_error(err, path))
+}
+
+fn lock_error(err: io::Error, path: &Path) -> Error {
+    if err.kind() == io::ErrorKind::WouldBlock
+        || err.raw_os_error() == fs2::lock_contended_error().raw_os_error()
+    {
+        FlumeLogError::LogLocked {
+            path: path.display().to_string(),
+        }
+        .into()
+    } else {
+        err.into()
+    }
+}
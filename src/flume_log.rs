@@ -1,4 +1,5 @@
-pub use failure::Error;
+pub use crate::error::Error;
+use thiserror::Error;
 
 pub struct StreamOpts {
     pub lt: String,
@@ -8,17 +9,72 @@ pub struct StreamOpts {
     pub limit: usize,
 }
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum FlumeLogError {
-    #[fail(display = "Unable to find sequence: {}", sequence)]
+    #[error("Unable to find sequence: {sequence}")]
     SequenceNotFound { sequence: u64 },
+    #[error("Log file is locked by another process: {path}")]
+    LogLocked { path: String },
+    #[error("Entry at sequence {sequence} is {expected} bytes long, not {found}")]
+    LengthMismatch {
+        sequence: u64,
+        expected: usize,
+        found: usize,
+    },
+    #[error("Entry was appended at sequence {found}, not the expected {expected}")]
+    UnexpectedSequence { expected: u64, found: u64 },
 }
 
 pub type Sequence = u64;
 
+/// The interface shared by every log backend.
+///
+/// The `conformance` module checks that an implementation behaves like the
+/// backends in this crate.
 pub trait FlumeLog {
     fn get(&self, seq: Sequence) -> Result<Vec<u8>, Error>;
-    fn clear(&mut self, seq: Sequence);
+    /// Erases the data of the entry at `seq`. The entry is still present in
+    /// the log, but reads back as zeroes.
+    fn clear(&mut self, seq: Sequence) -> Result<(), Error>;
     fn latest(&self) -> Option<Sequence>;
     fn append(&mut self, buff: &[u8]) -> Result<Sequence, Error>;
+
+    /// Reads the entries at `seqs`, returning a result for each, in the
+    /// same order. A missing or unreadable entry only fails its own result.
+    ///
+    /// Backends override this to read nearby entries together.
+    fn get_many(&self, seqs: &[Sequence]) -> Vec<Result<Vec<u8>, Error>> {
+        seqs.iter().map(|seq| self.get(*seq)).collect()
+    }
+}
+
+/// A log that knows which sequence an entry will be stored at before it's
+/// appended.
+pub trait NextSequence {
+    /// The sequence an entry of `len` bytes would get if it were appended
+    /// now.
+    fn next_sequence(&self, len: usize) -> Sequence;
+}
+
+/// A log whose entries can be overwritten in place.
+pub trait Overwrite {
+    /// Replaces the data of the entry at `seq` with `data`, failing with
+    /// `FlumeLogError::LengthMismatch` unless it's as long as the entry.
+    fn overwrite(&mut self, seq: Sequence, data: &[u8]) -> Result<(), Error>;
+}
+
+pub(crate) fn check_overwrite_length(
+    seq: Sequence,
+    entry: &[u8],
+    data: &[u8],
+) -> Result<(), Error> {
+    if entry.len() != data.len() {
+        return Err(FlumeLogError::LengthMismatch {
+            sequence: seq,
+            expected: entry.len(),
+            found: data.len(),
+        }
+        .into());
+    }
+    Ok(())
 }
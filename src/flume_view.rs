@@ -1,6 +1,23 @@
 pub use crate::flume_log::Sequence;
 
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+
 pub trait FlumeView {
     fn append(&mut self, seq: Sequence, item: &[u8]);
     fn latest(&self) -> Sequence;
 }
+
+/// Appends the entries of `log` that come after the one at `since` to
+/// `view`, or every entry if `since` is `None`.
+pub fn catch_up<V, L, I>(view: &mut V, log: &L, since: Option<Sequence>)
+where
+    V: FlumeView + ?Sized,
+    L: IterAtOffset<I>,
+    I: Iterator<Item = LogEntry>,
+{
+    let skip = if since.is_some() { 1 } else { 0 };
+    for entry in log.iter_at_offset(since.unwrap_or(0)).skip(skip) {
+        view.append(entry.offset, &entry.data);
+    }
+}
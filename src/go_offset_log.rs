@@ -1,5 +1,6 @@
 pub use bidir_iter::BidirIterator;
 
+use crate::file_lock::{lock_exclusive, lock_shared};
 use crate::flume_log::*;
 use crate::iter_at_offset::IterAtOffset;
 use crate::log_entry::LogEntry;
@@ -14,28 +15,40 @@ use std::io;
 use std::io::{Seek, SeekFrom};
 use std::mem::size_of;
 use std::path::Path;
+use thiserror::Error;
 
 const DATA_FILE_NAME: &str = "data";
+const OFFSET_FILE_NAME: &str = "ofst";
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum GoFlumeOffsetLogError {
-    #[fail(display = "Incorrect framing values detected, log file might be corrupt")]
+    #[error("Incorrect framing values detected, log file might be corrupt")]
     CorruptLogFile {},
-    #[fail(
-        display = "Incorrect values in journal file. File might be corrupt, or we might need better file locking."
+    #[error(
+        "Incorrect values in journal file. File might be corrupt, or we might need better file locking."
     )]
     CorruptJournalFile {},
-    #[fail(display = "Incorrect values in offset file. File might be corrupt.")]
+    #[error("Incorrect values in offset file. File might be corrupt.")]
     CorruptOffsetFile {},
-    #[fail(display = "Unsupported message type in offset log")]
+    #[error("Unsupported message type in offset log")]
     UnsupportedMessageType {},
 
-    #[fail(display = "The decode buffer passed to decode was too small")]
+    #[error("The decode buffer passed to decode was too small")]
     DecodeBufferSizeTooSmall {},
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid CBOR in entry: {0}")]
+    Cbor(#[from] serde_cbor::Error),
+    #[error("Invalid JSON in entry: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid UTF-8 in entry: {0}")]
+    Utf8(#[from] std::str::Utf8Error),
 }
 
 #[derive(Debug, Default, Deserialize)]
 struct GoMsgPackKey<'a> {
+    #[allow(dead_code)]
     #[serde(rename = "Algo")]
     algo: &'a str,
     #[serde(rename = "Hash")]
@@ -58,6 +71,7 @@ struct GoMsgPackData<'a> {
     raw: &'a str,
     #[serde(rename = "Key_")]
     key: GoMsgPackKey<'a>,
+    #[allow(dead_code)]
     #[serde(rename = "Sequence_")]
     sequence: u64,
 }
@@ -67,6 +81,8 @@ type GoCborTuple<'a> = (GoCborKey<'a>, CborValue, GoCborKey<'a>, i128, f64, &'a
 
 pub struct GoOffsetLog {
     pub data_file: File,
+    // The `ofst` file, which lists the offset of every entry in the data file.
+    offset_file: Option<File>,
     end_of_file: u64,
 }
 
@@ -91,6 +107,8 @@ pub struct ReadResult {
 
 impl GoOffsetLog {
     /// Where path is a path to the directory that contains go log files
+    ///
+    /// The data file is locked exclusively while the log is open.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<GoOffsetLog, Error> {
         let data_file_path = Path::new(path.as_ref()).join(DATA_FILE_NAME);
 
@@ -98,17 +116,26 @@ impl GoOffsetLog {
             .read(true)
             .write(true)
             .create(true)
-            .open(data_file_path)?;
+            .truncate(false)
+            .open(&data_file_path)?;
+        lock_exclusive(&data_file, &data_file_path)?;
 
-        GoOffsetLog::from_files(data_file)
+        let mut log = GoOffsetLog::from_files(data_file)?;
+        log.offset_file = open_offset_file(path.as_ref())?;
+        Ok(log)
     }
 
     /// Where path is a path to the directory that contains go log files
+    ///
+    /// The data file is locked in shared mode while the log is open.
     pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<GoOffsetLog, Error> {
         let data_file_path = Path::new(path.as_ref()).join(DATA_FILE_NAME);
         let file = OpenOptions::new().read(true).open(&data_file_path)?;
+        lock_shared(&file, &data_file_path)?;
 
-        GoOffsetLog::from_files(file)
+        let mut log = GoOffsetLog::from_files(file)?;
+        log.offset_file = open_offset_file(path.as_ref())?;
+        Ok(log)
     }
 
     pub fn from_files(mut data_file: File) -> Result<GoOffsetLog, Error> {
@@ -116,6 +143,7 @@ impl GoOffsetLog {
 
         Ok(GoOffsetLog {
             data_file,
+            offset_file: None,
             end_of_file: file_length,
         })
     }
@@ -124,10 +152,53 @@ impl GoOffsetLog {
         self.end_of_file
     }
 
+    /// Reads the entry at `offset`, failing with `FlumeLogError::SequenceNotFound`
+    /// if there's no entry there.
+    ///
+    /// If the log was opened from a directory with an `ofst` file, `offset`
+    /// is checked against the entry offsets it lists.
     pub fn read(&self, offset: u64) -> Result<ReadResult, Error> {
+        if offset >= self.end_of_file || !self.is_entry_start(offset)? {
+            return Err(FlumeLogError::SequenceNotFound { sequence: offset }.into());
+        }
         read_next::<_>(offset, &self.data_file)
     }
 
+    pub fn get(&self, seq: u64) -> Result<Vec<u8>, Error> {
+        self.read(seq).map(|r| r.entry.data)
+    }
+
+    fn is_entry_start(&self, offset: u64) -> Result<bool, Error> {
+        let file = match &self.offset_file {
+            Some(file) => file,
+            None => return Ok(true),
+        };
+
+        // The offsets are in ascending order, so binary search them.
+        let entry_size = size_of::<u64>() as u64;
+        let (mut lo, mut hi) = (0, file.metadata()?.len() / entry_size);
+        let mut buf = [0; size_of::<u64>()];
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            let n = file.read_at(&mut buf, mid * entry_size)?;
+            if n < buf.len() {
+                return Err(Error::GoOffsetLog {
+                    offset,
+                    source: GoFlumeOffsetLogError::CorruptOffsetFile {},
+                });
+            }
+            let entry_offset = (&buf[..]).read_u64::<BigEndian>()?;
+            if entry_offset == offset {
+                return Ok(true);
+            } else if entry_offset < offset {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        Ok(false)
+    }
+
     pub fn append_batch(&mut self, _buffs: &[&[u8]]) -> Result<Vec<u64>, Error> {
         unimplemented!()
     }
@@ -139,6 +210,14 @@ impl GoOffsetLog {
     }
 }
 
+fn open_offset_file(dir: &Path) -> Result<Option<File>, Error> {
+    match File::open(dir.join(OFFSET_FILE_NAME)) {
+        Ok(file) => Ok(Some(file)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
 pub struct GoOffsetLogIter {
     reader: BufOffsetReader<File>,
     current: u64,
@@ -187,11 +266,12 @@ fn read_next_impl<F>(offset: u64, mut read_at: F) -> Result<ReadResult, Error>
 where
     F: FnMut(&mut [u8], u64) -> io::Result<usize>,
 {
-    let frame = read_next_frame::<_>(offset, &mut read_at)?;
-    read_entry::<_>(&frame, &mut read_at)
+    read_next_frame::<_>(offset, &mut read_at)
+        .and_then(|frame| read_entry::<_>(&frame, &mut read_at))
+        .map_err(|source| Error::GoOffsetLog { offset, source })
 }
 
-fn read_next_frame<F>(offset: u64, read_at: &mut F) -> Result<Frame, Error>
+fn read_next_frame<F>(offset: u64, read_at: &mut F) -> Result<Frame, GoFlumeOffsetLogError>
 where
     F: FnMut(&mut [u8], u64) -> io::Result<usize>,
 {
@@ -202,14 +282,14 @@ where
     let mut head_bytes = [0; HEAD_SIZE];
     let n = read_at(&mut head_bytes, offset)?;
     if n < HEAD_SIZE {
-        return Err(GoFlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+        return Err(GoFlumeOffsetLogError::DecodeBufferSizeTooSmall {});
     }
 
     let data_size = (&head_bytes[..]).read_u64::<BigEndian>()? as usize;
     Ok(Frame { offset, data_size })
 }
 
-fn read_entry<F>(frame: &Frame, read_at: &mut F) -> Result<ReadResult, Error>
+fn read_entry<F>(frame: &Frame, read_at: &mut F) -> Result<ReadResult, GoFlumeOffsetLogError>
 where
     F: FnMut(&mut [u8], u64) -> io::Result<usize>,
 {
@@ -219,11 +299,11 @@ where
 
     let n = read_at(&mut buf, frame.data_start())?;
     if n < frame.data_size {
-        return Err(GoFlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+        return Err(GoFlumeOffsetLogError::DecodeBufferSizeTooSmall {});
     }
 
-    if buf[0] != 1 {
-        return Err(GoFlumeOffsetLogError::UnsupportedMessageType {}.into());
+    if buf.first() != Some(&1) {
+        return Err(GoFlumeOffsetLogError::UnsupportedMessageType {});
     }
 
     let tuple: GoCborTuple = from_slice(&buf[1..])?;
@@ -246,7 +326,7 @@ where
 
     let ssb_message = json!({
         "key": cbor.key.to_legacy_string(),
-        "value": serde_json::from_str::<Value>(&cbor.raw)?,
+        "value": serde_json::from_str::<Value>(cbor.raw)?,
         "timestamp": timestamp as u64
     });
 
@@ -268,6 +348,19 @@ mod test {
     use crate::go_offset_log::*;
     use serde_json::Value;
     use std::path::PathBuf;
+    use tempfile::{tempdir, TempDir};
+
+    // Opening a log for writing locks it, so tests that do so work on a copy.
+    fn copy_of_test_vec(name: &str) -> (TempDir, PathBuf) {
+        let mut src = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        src.push("test_vecs");
+        src.push(name);
+
+        let dir = tempdir().unwrap();
+        std::fs::copy(src.join(DATA_FILE_NAME), dir.path().join(DATA_FILE_NAME)).unwrap();
+        let path = dir.path().to_owned();
+        (dir, path)
+    }
 
     #[test]
     fn open_ro() {
@@ -296,8 +389,7 @@ mod test {
 
     #[test]
     fn ssb_messages() {
-        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        d.push("test_vecs/four_ssb_messages");
+        let (_dir, d) = copy_of_test_vec("four_ssb_messages");
         let log = GoOffsetLog::new(d).unwrap();
         let vec = log
             .iter()
@@ -309,4 +401,39 @@ mod test {
         assert_eq!(vec[0]["value"]["previous"], Value::Null);
         assert_eq!(vec[1]["value"]["content"]["hello"], "piet!!!");
     }
+
+    #[test]
+    fn writer_locks_out_other_handles() {
+        let (_dir, d) = copy_of_test_vec("four_ssb_messages");
+
+        let log = GoOffsetLog::new(&d).unwrap();
+        match GoOffsetLog::open_read_only(&d) {
+            Err(Error::Log(FlumeLogError::LogLocked { .. })) => {}
+            _ => panic!("expected LogLocked"),
+        }
+        assert!(GoOffsetLog::new(&d).is_err());
+
+        drop(log);
+        let a = GoOffsetLog::open_read_only(&d).unwrap();
+        let b = GoOffsetLog::open_read_only(&d).unwrap();
+        assert_eq!(a.end(), b.end());
+    }
+
+    #[test]
+    fn get_missing_sequence() {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("test_vecs/four_ssb_messages");
+        let log = GoOffsetLog::open_read_only(d).unwrap();
+        let not_found = |seq| match log.get(seq) {
+            Err(Error::Log(FlumeLogError::SequenceNotFound { sequence })) => sequence == seq,
+            _ => false,
+        };
+
+        assert!(log.get(0).is_ok());
+        assert!(log.get(447).is_ok());
+        assert!(not_found(8));
+        assert!(not_found(446));
+        assert!(not_found(log.end()));
+        assert!(not_found(log.end() + 1000));
+    }
 }
@@ -0,0 +1,381 @@
+use crate::atomic_file::{read_if_exists, write_atomically};
+use crate::flume_log::*;
+use crate::flume_view::*;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+
+/// The function an [`IndexView`] gets the keys of each log entry with.
+/// Entries may have any number of keys, including none.
+pub type KeyExtractor<K> = fn(Sequence, &[u8]) -> Vec<K>;
+
+/// An ordered index from keys to the sequences of the entries that have
+/// them, like the JS `flumeview-level`.
+///
+/// ```
+/// use flumedb::{FlumeLog, IndexView, MemLog};
+/// use serde_json::Value;
+///
+/// let mut log = MemLog::new();
+/// log.append(br#"{"timestamp": 30}"#).unwrap();
+/// log.append(br#"{"timestamp": 10}"#).unwrap();
+/// log.append(br#"{"timestamp": 20}"#).unwrap();
+///
+/// let mut by_time = IndexView::new(1, |_seq, data| {
+///     let v: Value = serde_json::from_slice(data).unwrap();
+///     v["timestamp"].as_u64().into_iter().collect()
+/// });
+/// by_time.catch_up(&log);
+///
+/// let seqs: Vec<u64> = by_time.range(15..).map(|(_, seq)| seq).collect();
+/// assert_eq!(seqs, &[2, 0]);
+/// let seqs: Vec<u64> = by_time.range(..).rev().map(|(_, seq)| seq).collect();
+/// assert_eq!(seqs, &[0, 2, 1]);
+/// ```
+pub struct IndexView<K> {
+    version: u32,
+    extract: KeyExtractor<K>,
+    index: BTreeMap<K, BTreeSet<Sequence>>,
+    since: Option<Sequence>,
+    path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedIndex<I> {
+    version: u32,
+    seq: Option<Sequence>,
+    index: I,
+}
+
+impl<K: Ord> IndexView<K> {
+    /// Creates an index that's kept in memory only.
+    ///
+    /// `version` identifies the key extractor; bump it whenever `extract`
+    /// changes, so that saved indexes are rebuilt rather than reused.
+    pub fn new(version: u32, extract: KeyExtractor<K>) -> IndexView<K> {
+        IndexView {
+            version,
+            extract,
+            index: BTreeMap::new(),
+            since: None,
+            path: None,
+        }
+    }
+
+    /// The sequences of the entries with the key `key`, in ascending order.
+    pub fn get<Q>(&self, key: &Q) -> Vec<Sequence>
+    where
+        K: Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.index
+            .get(key)
+            .map(|seqs| seqs.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// The keys in `range` and the sequences of the entries that have them,
+    /// in key order. Call `rev()` on the result to scan in reverse.
+    ///
+    /// Unlike `BTreeMap::range`, a range that ends before it starts is
+    /// empty rather than a panic.
+    pub fn range<R>(&self, range: R) -> impl DoubleEndedIterator<Item = (&K, Sequence)>
+    where
+        R: RangeBounds<K>,
+    {
+        let valid = is_valid_range(range.start_bound(), range.end_bound());
+        valid
+            .then(|| self.index.range(range))
+            .into_iter()
+            .flatten()
+            .flat_map(|(key, seqs)| seqs.iter().map(move |seq| (key, *seq)))
+    }
+
+    /// Up to `limit` of the entries in `range`, in key order, or in reverse
+    /// key order if `reverse` is set, starting just after the entry `after`.
+    ///
+    /// Pass the last entry of one page as `after` to get the next.
+    pub fn page<R>(
+        &self,
+        range: R,
+        after: Option<&(K, Sequence)>,
+        reverse: bool,
+        limit: usize,
+    ) -> Vec<(K, Sequence)>
+    where
+        K: Clone,
+        R: RangeBounds<K>,
+    {
+        let mut lower = range.start_bound().cloned();
+        let mut upper = range.end_bound().cloned();
+        // Skip straight to the key of `after` rather than scanning up to it.
+        if let Some((key, _)) = after {
+            if reverse && is_past(&upper, key, Ordering::Greater) {
+                upper = Bound::Included(key.clone());
+            } else if !reverse && is_past(&lower, key, Ordering::Less) {
+                lower = Bound::Included(key.clone());
+            }
+        }
+
+        let is_after = |(key, seq): &(&K, Sequence)| match after {
+            Some((k, s)) if reverse => (*key, *seq) >= (k, *s),
+            Some((k, s)) => (*key, *seq) <= (k, *s),
+            None => false,
+        };
+        let entries = self.range((lower, upper));
+        let page: Vec<(&K, Sequence)> = if reverse {
+            entries.rev().skip_while(is_after).take(limit).collect()
+        } else {
+            entries.skip_while(is_after).take(limit).collect()
+        };
+        page.into_iter()
+            .map(|(key, seq)| (key.clone(), seq))
+            .collect()
+    }
+
+    /// The number of distinct keys in the index.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// The sequence of the last entry added to the index, if any.
+    pub fn since(&self) -> Option<Sequence> {
+        self.since
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Indexes the entries of `log` that haven't been indexed yet. An index
+    /// that has just been created (or whose saved state was missing or out
+    /// of date) is built from the whole log.
+    pub fn catch_up<L, I>(&mut self, log: &L)
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        let since = self.since;
+        catch_up(self, log, since);
+    }
+
+    /// Empties the index, so it can be rebuilt from the start of the log.
+    pub fn reset(&mut self) {
+        self.index.clear();
+        self.since = None;
+    }
+}
+
+/// Whether `bound` is unbounded, or its key is on the `side` side of `key`.
+fn is_past<K: Ord>(bound: &Bound<K>, key: &K, side: Ordering) -> bool {
+    match bound {
+        Bound::Included(k) | Bound::Excluded(k) => k.cmp(key) == side,
+        Bound::Unbounded => true,
+    }
+}
+
+/// Whether `BTreeMap::range` accepts the bounds without panicking.
+fn is_valid_range<K: Ord>(lower: Bound<&K>, upper: Bound<&K>) -> bool {
+    match (lower, upper) {
+        (Bound::Included(l), Bound::Included(u)) => l <= u,
+        (Bound::Included(l), Bound::Excluded(u)) | (Bound::Excluded(l), Bound::Included(u)) => {
+            l <= u
+        }
+        (Bound::Excluded(l), Bound::Excluded(u)) => l < u,
+        _ => true,
+    }
+}
+
+impl<K: Ord + Serialize + DeserializeOwned> IndexView<K> {
+    /// Creates an index that's saved to the file at `path`.
+    ///
+    /// If the file holds an index saved with the same `version`, the index
+    /// starts from there; otherwise it starts out empty.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        version: u32,
+        extract: KeyExtractor<K>,
+    ) -> Result<IndexView<K>, Error> {
+        let mut view = IndexView::new(version, extract);
+        view.path = Some(path.as_ref().to_owned());
+
+        if let Some(bytes) = read_if_exists(path.as_ref())? {
+            let saved: SavedIndex<BTreeMap<K, BTreeSet<Sequence>>> =
+                serde_cbor::from_slice(&bytes)?;
+            if saved.version == version {
+                view.index = saved.index;
+                view.since = saved.seq;
+            }
+        }
+        Ok(view)
+    }
+
+    /// Writes the index to its file. Does nothing for in-memory indexes.
+    pub fn save(&self) -> Result<(), Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let saved = SavedIndex {
+            version: self.version,
+            seq: self.since,
+            index: &self.index,
+        };
+        write_atomically(path, &serde_cbor::to_vec(&saved)?)
+    }
+}
+
+impl<K: Ord> FlumeView for IndexView<K> {
+    fn append(&mut self, seq: Sequence, item: &[u8]) {
+        for key in (self.extract)(seq, item) {
+            self.index.entry(key).or_default().insert(seq);
+        }
+        self.since = Some(seq);
+    }
+
+    /// The sequence of the last entry added to the index, or zero if none have been.
+    fn latest(&self) -> Sequence {
+        self.since.unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::*;
+    use crate::index_view::*;
+    use crate::offset_log::OffsetLog;
+    use serde_json::Value;
+    use tempfile::{tempdir, tempfile};
+
+    fn words(_seq: Sequence, data: &[u8]) -> Vec<String> {
+        String::from_utf8_lossy(data)
+            .split_whitespace()
+            .map(|w| w.to_string())
+            .collect()
+    }
+
+    fn value(_seq: Sequence, data: &[u8]) -> Vec<u64> {
+        serde_json::from_slice::<Value>(data)
+            .ok()
+            .and_then(|v| v["value"].as_u64())
+            .into_iter()
+            .collect()
+    }
+
+    fn words_log() -> Result<(OffsetLog<u32>, Vec<Sequence>), Error> {
+        let mut log = OffsetLog::<u32>::from_file(tempfile()?)?;
+        let seqs = log.append_batch(&["cherry apple", "", "banana apple", "date"])?;
+        Ok((log, seqs))
+    }
+
+    #[test]
+    fn lookups_and_scans() -> Result<(), Error> {
+        let (log, seqs) = words_log()?;
+        let mut view = IndexView::new(1, words);
+        view.catch_up(&log);
+
+        assert_eq!(view.len(), 4);
+        assert_eq!(view.since(), Some(seqs[3]));
+        assert_eq!(view.get("apple"), &[seqs[0], seqs[2]]);
+        assert!(view.get("fig").is_empty());
+
+        let keys: Vec<&str> = view
+            .range("b".to_string().."d".to_string())
+            .map(|(k, _)| k.as_str())
+            .collect();
+        assert_eq!(keys, &["banana", "cherry"]);
+
+        let scan: Vec<(&str, Sequence)> = view
+            .range(..)
+            .rev()
+            .map(|(k, seq)| (k.as_str(), seq))
+            .collect();
+        assert_eq!(
+            scan,
+            &[
+                ("date", seqs[3]),
+                ("cherry", seqs[0]),
+                ("banana", seqs[2]),
+                ("apple", seqs[2]),
+                ("apple", seqs[0]),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pages() {
+        let mut view = IndexView::new(1, |seq, _| vec![seq / 3]);
+        for seq in 0..10 {
+            view.append(seq, b"");
+        }
+
+        let forward = view.page(1.., None, false, 4);
+        assert_eq!(forward, &[(1, 3), (1, 4), (1, 5), (2, 6)]);
+        let forward = view.page(1.., forward.last(), false, 4);
+        assert_eq!(forward, &[(2, 7), (2, 8), (3, 9)]);
+        assert!(view.page(1.., forward.last(), false, 4).is_empty());
+
+        let back = view.page(..=2, None, true, 4);
+        assert_eq!(back, &[(2, 8), (2, 7), (2, 6), (1, 5)]);
+        let back = view.page(..=2, back.last(), true, 4);
+        assert_eq!(back, &[(1, 4), (1, 3), (0, 2), (0, 1)]);
+        let back = view.page(..=2, back.last(), true, 4);
+        assert_eq!(back, &[(0, 0)]);
+
+        // A cursor outside the range doesn't widen it.
+        assert_eq!(view.page(1..2, Some(&(3, 9)), true, 10).len(), 3);
+        assert!(view.page(1..2, Some(&(3, 9)), false, 10).is_empty());
+        assert!(view
+            .range((Bound::Included(2), Bound::Excluded(1)))
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn index_js_log() {
+        let log = OffsetLog::<u32>::open_read_only("./db/test.offset").unwrap();
+        let mut view = IndexView::new(1, value);
+        view.catch_up(&log);
+
+        let values: Vec<u64> = view.range(3..=5).map(|(v, _)| *v).collect();
+        assert_eq!(values, &[3, 4, 5]);
+        assert_eq!(view.get(&0), &[0]);
+    }
+
+    #[test]
+    fn save_open_and_catch_up() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("words.cbor");
+        let (mut log, seqs) = words_log()?;
+
+        let mut view = IndexView::open(&path, 1, words)?;
+        assert!(view.is_empty());
+        view.catch_up(&log);
+        view.save()?;
+
+        let e = log.append(b"elderberry apple")?;
+        let mut view = IndexView::open(&path, 1, words)?;
+        assert_eq!(view.since(), Some(seqs[3]));
+        view.catch_up(&log);
+        assert_eq!(view.get("apple"), &[seqs[0], seqs[2], e]);
+        assert_eq!(view.since(), Some(e));
+
+        // A new version of the index is rebuilt from scratch.
+        let mut view = IndexView::open(&path, 2, words)?;
+        assert!(view.is_empty());
+        view.catch_up(&log);
+        assert_eq!(view.get("apple"), &[seqs[0], seqs[2], e]);
+        Ok(())
+    }
+}
@@ -0,0 +1,263 @@
+//! A JSON value that keeps the order of object fields, and an encoder for it
+//! that matches JavaScript's `JSON.stringify`.
+
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde_json::{Map, Number, Value};
+use std::fmt;
+
+/// A JSON value that keeps the order of object fields, which matters to
+/// the signatures and keys of SSB messages, and to their BIPF encoding.
+/// (`serde_json::Value` sorts them.)
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl From<&Value> for Json {
+    fn from(value: &Value) -> Json {
+        match value {
+            Value::Null => Json::Null,
+            Value::Bool(b) => Json::Bool(*b),
+            Value::Number(n) => Json::Number(n.clone()),
+            Value::String(s) => Json::String(s.clone()),
+            Value::Array(items) => Json::Array(items.iter().map(Json::from).collect()),
+            Value::Object(fields) => Json::Object(
+                fields
+                    .iter()
+                    .map(|(name, value)| (name.clone(), Json::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<Json> for Value {
+    fn from(json: Json) -> Value {
+        match json {
+            Json::Null => Value::Null,
+            Json::Bool(b) => Value::Bool(b),
+            Json::Number(n) => Value::Number(n),
+            Json::String(s) => Value::String(s),
+            Json::Array(items) => Value::Array(items.into_iter().map(Value::from).collect()),
+            Json::Object(fields) => Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(name, value)| (name, Value::from(value)))
+                    .collect::<Map<String, Value>>(),
+            ),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Json {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Json, D::Error> {
+        deserializer.deserialize_any(JsonVisitor)
+    }
+}
+
+struct JsonVisitor;
+
+impl<'de> Visitor<'de> for JsonVisitor {
+    type Value = Json;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("any JSON value")
+    }
+
+    fn visit_unit<E>(self) -> Result<Json, E> {
+        Ok(Json::Null)
+    }
+
+    fn visit_bool<E>(self, b: bool) -> Result<Json, E> {
+        Ok(Json::Bool(b))
+    }
+
+    fn visit_i64<E>(self, n: i64) -> Result<Json, E> {
+        Ok(Json::Number(n.into()))
+    }
+
+    fn visit_u64<E>(self, n: u64) -> Result<Json, E> {
+        Ok(Json::Number(n.into()))
+    }
+
+    fn visit_f64<E>(self, n: f64) -> Result<Json, E> {
+        // JSON can't hold NaN or infinities, so this can't fail.
+        Ok(Json::Number(Number::from_f64(n).unwrap()))
+    }
+
+    fn visit_str<E>(self, s: &str) -> Result<Json, E> {
+        Ok(Json::String(s.to_string()))
+    }
+
+    fn visit_string<E>(self, s: String) -> Result<Json, E> {
+        Ok(Json::String(s))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Json, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Json::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Json, A::Error> {
+        let mut fields = Vec::new();
+        while let Some(field) = map.next_entry()? {
+            fields.push(field);
+        }
+        Ok(Json::Object(fields))
+    }
+}
+
+/// Encodes `value` like JavaScript's `JSON.stringify`, indented by two
+/// spaces if `pretty` is set, as SSB messages are when signed and hashed.
+pub(crate) fn encode(value: &Json, pretty: bool) -> String {
+    let mut out = String::new();
+    write_json(&mut out, value, pretty, 0);
+    out
+}
+
+pub(crate) fn write_json(out: &mut String, value: &Json, pretty: bool, depth: usize) {
+    match value {
+        Json::Null => out.push_str("null"),
+        Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Json::Number(n) => match n.as_f64() {
+            Some(f) if !n.is_u64() && !n.is_i64() => out.push_str(&js_number(f)),
+            _ => out.push_str(&n.to_string()),
+        },
+        Json::String(s) => write_str(out, s),
+        Json::Array(items) if items.is_empty() => out.push_str("[]"),
+        Json::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                new_line(out, pretty, depth + 1);
+                write_json(out, item, pretty, depth + 1);
+            }
+            new_line(out, pretty, depth);
+            out.push(']');
+        }
+        Json::Object(fields) if fields.is_empty() => out.push_str("{}"),
+        Json::Object(fields) => {
+            out.push('{');
+            for (i, (name, value)) in fields.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                new_line(out, pretty, depth + 1);
+                write_str(out, name);
+                out.push_str(if pretty { ": " } else { ":" });
+                write_json(out, value, pretty, depth + 1);
+            }
+            new_line(out, pretty, depth);
+            out.push('}');
+        }
+    }
+}
+
+fn new_line(out: &mut String, pretty: bool, depth: usize) {
+    if pretty {
+        out.push('\n');
+        for _ in 0..depth {
+            out.push_str("  ");
+        }
+    }
+}
+
+pub(crate) fn write_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// Formats `n` like JavaScript's `Number.prototype.toString`.
+fn js_number(n: f64) -> String {
+    if n == 0.0 {
+        return "0".to_string();
+    }
+    // Rust and JavaScript agree on the shortest digits that round trip;
+    // they only differ on where the decimal point goes.
+    let sci = format!("{:e}", n.abs());
+    let (mantissa, exponent) = sci.split_at(sci.find('e').unwrap());
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    let k = digits.len() as i32;
+    let point = exponent[1..].parse::<i32>().unwrap() + 1;
+
+    let body = if k <= point && point <= 21 {
+        format!("{}{}", digits, "0".repeat((point - k) as usize))
+    } else if 0 < point && point <= 21 {
+        format!(
+            "{}.{}",
+            &digits[..point as usize],
+            &digits[point as usize..]
+        )
+    } else if -6 < point && point <= 0 {
+        format!("0.{}{}", "0".repeat(-point as usize), digits)
+    } else {
+        let e = point - 1;
+        let sign = if e < 0 { '-' } else { '+' };
+        let mantissa = if k == 1 {
+            digits
+        } else {
+            format!("{}.{}", &digits[..1], &digits[1..])
+        };
+        format!("{}e{}{}", mantissa, sign, e.abs())
+    };
+    if n < 0.0 {
+        format!("-{}", body)
+    } else {
+        body
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::json::*;
+
+    #[test]
+    fn canonical_encoding() {
+        let value: Json =
+            serde_json::from_str(r#"{"b": [1, 2.5, {}], "a": {"x": "é\n\u0001"}, "c": []}"#)
+                .unwrap();
+        assert_eq!(
+            encode(&value, true),
+            "{\n  \"b\": [\n    1,\n    2.5,\n    {}\n  ],\n  \"a\": {\n    \"x\": \"é\\n\\u0001\"\n  },\n  \"c\": []\n}"
+        );
+        assert_eq!(
+            encode(&value, false),
+            r#"{"b":[1,2.5,{}],"a":{"x":"é\n\u0001"},"c":[]}"#
+        );
+
+        for (n, js) in &[
+            (1553532843013.002, "1553532843013.002"),
+            (1.0, "1"),
+            (-0.5, "-0.5"),
+            (1e21, "1e+21"),
+            (1.5e-7, "1.5e-7"),
+            (0.000001, "0.000001"),
+            (123e18, "123000000000000000000"),
+        ] {
+            assert_eq!(js_number(*n), *js);
+        }
+    }
+}
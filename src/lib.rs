@@ -6,27 +6,66 @@ extern crate bidir_iter;
 extern crate buffered_offset_reader;
 extern crate byteorder;
 extern crate bytes;
-#[macro_use]
-extern crate failure;
+extern crate fs2;
 extern crate log;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
-extern crate serde_json;
 extern crate serde_cbor;
+extern crate serde_json;
 extern crate ssb_multiformats;
 
-
+pub mod aligned_offset_log;
+#[cfg(feature = "async")]
+pub mod async_log;
+mod atomic_file;
+pub mod bipf;
+pub mod bitvector_index;
+pub mod codec;
+pub mod compressed_log;
+pub mod conformance;
+pub mod encrypted_log;
+pub mod error;
+mod file_lock;
 pub mod flume_log;
 pub mod flume_view;
 pub mod go_offset_log;
+pub mod index_view;
 pub mod iter_at_offset;
+mod json;
 pub mod log_entry;
 pub mod mem_log;
 pub mod offset_log;
+pub mod query;
+pub mod reduce_view;
+pub mod ssb_feed_view;
+pub mod ssb_key_view;
+pub mod ssb_links_view;
+pub mod ssb_timestamp_view;
+pub mod ssb_type_view;
+pub mod ssb_unbox_view;
+pub mod ssb_validating_log;
+pub mod typed_log;
 
+pub use aligned_offset_log::*;
+#[cfg(feature = "async")]
+pub use async_log::*;
+pub use bitvector_index::*;
+pub use compressed_log::*;
+pub use encrypted_log::*;
+pub use error::Error;
 pub use flume_log::*;
 pub use flume_view::*;
+pub use index_view::*;
 pub use iter_at_offset::*;
 pub use mem_log::*;
 pub use offset_log::*;
+pub use reduce_view::*;
+pub use ssb_feed_view::*;
+pub use ssb_key_view::*;
+pub use ssb_links_view::*;
+pub use ssb_timestamp_view::*;
+pub use ssb_type_view::*;
+pub use ssb_unbox_view::*;
+pub use ssb_validating_log::*;
+pub use typed_log::*;
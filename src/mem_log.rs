@@ -1,59 +1,206 @@
+pub use bidir_iter::{BidirIterator, Forward};
+
 use crate::flume_log::*;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use crate::offset_log::{encode, read_next};
 
+use bytes::BytesMut;
 use std::iter::IntoIterator;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+
+// Entries are shared with any iterators over the log, which (like the
+// iterators over an `OffsetLog`) see entries appended after they were created.
+type Entries = Arc<RwLock<Vec<Vec<u8>>>>;
 
 pub struct MemLog {
-    log: Vec<Vec<u8>>,
+    log: Entries,
 }
 
 impl MemLog {
     pub fn new() -> MemLog {
-        let log = Vec::new();
+        let log = Arc::new(RwLock::new(Vec::new()));
         MemLog { log }
     }
+
+    /// Copies every entry of another log into a new `MemLog`.
+    ///
+    /// Entries keep their order, but are renumbered from zero.
+    pub fn from_log<L, I>(log: &L) -> MemLog
+    where
+        L: FlumeLog + IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        let entries = log.iter_at_offset(0).map(|e| e.data).collect();
+        MemLog {
+            log: Arc::new(RwLock::new(entries)),
+        }
+    }
+
+    /// Reads a log file in the JS `flumelog-offset` format (the format of
+    /// `OffsetLog<ByteType>`) into memory.
+    pub fn load<ByteType, P: AsRef<Path>>(path: P) -> Result<MemLog, Error> {
+        let bytes = std::fs::read(path)?;
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() as u64 {
+            let r = read_next::<ByteType, _>(offset, &bytes.as_slice())?;
+            entries.push(r.entry.data);
+            offset = r.next;
+        }
+        Ok(MemLog {
+            log: Arc::new(RwLock::new(entries)),
+        })
+    }
+
+    /// Writes the log to `path` in the JS `flumelog-offset` format, replacing
+    /// any existing file. The result can be opened with `OffsetLog<ByteType>`.
+    pub fn save<ByteType, P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let mut bytes = BytesMut::new();
+        self.log
+            .read()
+            .unwrap()
+            .iter()
+            .try_fold(0, |offset, data| {
+                encode::<ByteType>(offset, data, &mut bytes)
+            })?;
+        std::fs::write(path, &bytes)?;
+        Ok(())
+    }
+
+    pub fn iter(&self) -> Forward<MemLogIter> {
+        self.bidir_iter().forward_owned()
+    }
+
+    pub fn bidir_iter(&self) -> MemLogIter {
+        self.bidir_iter_at_offset(0)
+    }
+
+    /// The offset of an entry in a `MemLog` is its sequence number.
+    pub fn bidir_iter_at_offset(&self, offset: u64) -> MemLogIter {
+        MemLogIter {
+            log: self.log.clone(),
+            current: offset,
+            next: offset,
+        }
+    }
+}
+
+impl Default for MemLog {
+    fn default() -> MemLog {
+        MemLog::new()
+    }
+}
+
+impl NextSequence for MemLog {
+    fn next_sequence(&self, _len: usize) -> u64 {
+        self.log.read().unwrap().len() as u64
+    }
+}
+
+impl Overwrite for MemLog {
+    fn overwrite(&mut self, seq: u64, data: &[u8]) -> Result<(), Error> {
+        let mut log = self.log.write().unwrap();
+        let entry = log
+            .get_mut(seq as usize)
+            .ok_or(FlumeLogError::SequenceNotFound { sequence: seq })?;
+        check_overwrite_length(seq, entry, data)?;
+        entry.copy_from_slice(data);
+        Ok(())
+    }
 }
 
 impl FlumeLog for MemLog {
     fn get(&self, seq_num: u64) -> Result<Vec<u8>, Error> {
         self.log
+            .read()
+            .unwrap()
             .get(seq_num as usize)
-            .map(|slice| slice.clone())
+            .cloned()
             .ok_or(FlumeLogError::SequenceNotFound { sequence: seq_num }.into())
     }
-    fn clear(&mut self, seq: u64) {
-        self.log[seq as usize] = Vec::new();
+    fn clear(&mut self, seq: u64) -> Result<(), Error> {
+        let mut log = self.log.write().unwrap();
+        let data = log
+            .get_mut(seq as usize)
+            .ok_or(FlumeLogError::SequenceNotFound { sequence: seq })?;
+        data.iter_mut().for_each(|b| *b = 0);
+        Ok(())
     }
     fn latest(&self) -> Option<u64> {
-        if self.log.len() == 0 {
+        let log = self.log.read().unwrap();
+        if log.is_empty() {
             None
         } else {
-            Some(self.log.len() as u64 - 1)
+            Some(log.len() as u64 - 1)
         }
     }
     fn append(&mut self, buff: &[u8]) -> Result<u64, Error> {
-        let seq = self.log.len();
+        let mut log = self.log.write().unwrap();
+        let seq = log.len();
         let mut vec = Vec::new();
         vec.extend_from_slice(buff);
 
-        self.log.push(vec);
+        log.push(vec);
 
         Ok(seq as u64)
     }
 }
 
-impl<'a> IntoIterator for &'a MemLog {
-    type Item = &'a Vec<u8>;
-    type IntoIter = std::slice::Iter<'a, Vec<u8>>;
+impl IntoIterator for &MemLog {
+    type Item = LogEntry;
+    type IntoIter = Forward<MemLogIter>;
 
     fn into_iter(self) -> Self::IntoIter {
-        self.log.iter()
+        self.iter()
+    }
+}
+
+impl IterAtOffset<Forward<MemLogIter>> for MemLog {
+    fn iter_at_offset(&self, offset: u64) -> Forward<MemLogIter> {
+        self.bidir_iter_at_offset(offset).forward_owned()
+    }
+}
+
+pub struct MemLogIter {
+    log: Entries,
+    current: u64,
+    next: u64,
+}
+
+impl MemLogIter {
+    fn entry(&self, seq: u64) -> Option<LogEntry> {
+        let data = self.log.read().unwrap().get(seq as usize)?.clone();
+        Some(LogEntry { offset: seq, data })
+    }
+}
+
+impl BidirIterator for MemLogIter {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.current = self.next;
+        let entry = self.entry(self.current)?;
+        self.next = self.current + 1;
+        Some(entry)
+    }
+
+    fn prev(&mut self) -> Option<Self::Item> {
+        self.next = self.current;
+        let entry = self.entry(self.current.checked_sub(1)?)?;
+        self.current = entry.offset;
+        Some(entry)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::flume_log::*;
-    use crate::mem_log::MemLog;
+    use crate::iter_at_offset::IterAtOffset;
+    use crate::mem_log::*;
+    use crate::offset_log::OffsetLog;
+    use tempfile::{tempdir, tempfile};
     #[test]
     fn get() {
         let mut log = MemLog::new();
@@ -61,19 +208,19 @@ mod tests {
 
         match log.get(seq0) {
             Ok(result) => assert_eq!(String::from_utf8_lossy(&result), "Hello"),
-            _ => assert!(false),
+            _ => panic!(),
         }
     }
     #[test]
     fn clear() {
         let mut log = MemLog::new();
         let seq0 = log.append("Hello".as_bytes()).unwrap();
-        log.clear(seq0);
+        log.clear(seq0).unwrap();
         match log.get(seq0) {
             Ok(result) => {
-                assert_eq!(result.len(), 0);
+                assert_eq!(result, &[0; 5]);
             }
-            _ => assert!(false),
+            _ => panic!(),
         }
     }
     #[test]
@@ -85,7 +232,7 @@ mod tests {
 
         let result = log
             .into_iter()
-            .map(|bytes| String::from_utf8_lossy(bytes))
+            .map(|e| String::from_utf8(e.data).unwrap())
             .fold(String::new(), |mut acc: String, elem| {
                 acc.push_str(&elem);
                 acc
@@ -99,7 +246,114 @@ mod tests {
 
         match log.get(seq0) {
             Ok(result) => assert_eq!(String::from_utf8_lossy(&result), "Hello"),
-            _ => assert!(false),
+            _ => panic!(),
         }
     }
+
+    #[test]
+    fn bidir_iter() -> Result<(), Error> {
+        let mut log = MemLog::new();
+        log.append(b"abc")?;
+        log.append(b"def")?;
+        log.append(b"123")?;
+
+        let mut iter = log.bidir_iter();
+        assert_eq!(iter.next().unwrap().data, b"abc");
+        assert_eq!(iter.next().unwrap().data, b"def");
+        assert_eq!(iter.next().unwrap().data, b"123");
+        assert!(iter.next().is_none());
+        assert_eq!(iter.prev().unwrap().data, b"123");
+        assert_eq!(iter.prev().unwrap().data, b"def");
+        assert_eq!(iter.prev().unwrap().data, b"abc");
+        assert!(iter.prev().is_none());
+        assert_eq!(iter.next().unwrap().data, b"abc");
+
+        let mut iter = log.bidir_iter().map(|e| e.offset);
+        let forward: Vec<u64> = iter.forward().collect();
+        assert_eq!(forward, &[0, 1, 2]);
+        let backward: Vec<u64> = iter.backward().collect();
+        assert_eq!(backward, &[2, 1, 0]);
+
+        let backward: Vec<u64> = log
+            .bidir_iter_at_offset(log.latest().unwrap() + 1)
+            .backward()
+            .map(|e| e.offset)
+            .collect();
+        assert_eq!(backward, &[2, 1, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn iter_at_offset() -> Result<(), Error> {
+        let mut log = MemLog::new();
+        log.append(b"abc")?;
+        log.append(b"def")?;
+
+        let mut iter = log.iter_at_offset(1);
+        assert_eq!(iter.next().unwrap().data, b"def");
+        assert!(iter.next().is_none());
+
+        // Iterators see entries appended after they were created.
+        log.append(b"ghi")?;
+        let e = iter.next().unwrap();
+        assert_eq!((e.offset, e.data), (2, b"ghi".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn save_and_load() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log.offset");
+
+        let mut log = MemLog::new();
+        log.append(b"abc")?;
+        log.append(b"")?;
+        log.append(b"def")?;
+        log.save::<u32, _>(&path)?;
+
+        let offset_log = OffsetLog::<u32>::open_read_only(&path)?;
+        let data: Vec<Vec<u8>> = offset_log.iter().map(|e| e.data).collect();
+        assert_eq!(data, vec![b"abc".to_vec(), vec![], b"def".to_vec()]);
+
+        let loaded = MemLog::load::<u32, _>(&path)?;
+        assert_eq!(loaded.latest(), Some(2));
+        assert_eq!(loaded.get(2)?, b"def");
+        Ok(())
+    }
+
+    #[test]
+    fn load_js_log() -> Result<(), Error> {
+        let log = MemLog::load::<u32, _>("./db/test.offset")?;
+        let offset_log = OffsetLog::<u32>::open_read_only("./db/test.offset")?;
+
+        let expected: Vec<Vec<u8>> = offset_log.iter().map(|e| e.data).collect();
+        let actual: Vec<Vec<u8>> = log.iter().map(|e| e.data).collect();
+        assert_eq!(actual, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn load_truncated_log() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log.offset");
+        let mut bytes = std::fs::read("./db/test.offset")?;
+        bytes.pop();
+        std::fs::write(&path, &bytes)?;
+
+        assert!(MemLog::load::<u32, _>(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn from_log() -> Result<(), Error> {
+        let mut offset_log = OffsetLog::<u32>::from_file(tempfile()?)?;
+        offset_log.append(b"abc")?;
+        offset_log.append(b"def")?;
+
+        let log = MemLog::from_log(&offset_log);
+        assert_eq!(log.latest(), Some(1));
+        assert_eq!(log.get(0)?, b"abc");
+        assert_eq!(log.get(1)?, b"def");
+        Ok(())
+    }
 }
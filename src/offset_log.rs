@@ -1,5 +1,6 @@
 pub use bidir_iter::{BidirIterator, Forward};
 
+use crate::file_lock::{lock_exclusive, lock_shared};
 use crate::flume_log::*;
 use crate::iter_at_offset::IterAtOffset;
 use crate::log_entry::LogEntry;
@@ -12,19 +13,26 @@ use std::io::{Seek, SeekFrom};
 use std::marker::PhantomData;
 use std::mem::size_of;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
 
-#[derive(Debug, Fail)]
+#[derive(Debug, Error)]
 pub enum FlumeOffsetLogError {
-    #[fail(display = "Incorrect framing values detected, log file might be corrupt")]
+    #[error("Incorrect framing values detected, log file might be corrupt")]
     CorruptLogFile {},
 
-    #[fail(display = "The decode buffer passed to decode was too small")]
+    #[error("The decode buffer passed to decode was too small")]
     DecodeBufferSizeTooSmall {},
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
 }
 
 pub struct OffsetLog<ByteType> {
     pub file: File,
     end_of_file: u64,
+    committed_end: Arc<AtomicU64>,
     last_offset: Option<u64>,
     tmp_buffer: BytesMut,
     byte_type: PhantomData<ByteType>,
@@ -43,6 +51,13 @@ impl Frame {
     }
 }
 
+// Requested entries closer together than this are read together in
+// `get_many`, as are groups up to `MAX_COALESCED_READ` bytes long. Reading
+// a little past the last one saves another read for it.
+const COALESCE_GAP: u64 = 64 * 1024;
+const MAX_COALESCED_READ: u64 = 1024 * 1024;
+const READ_AHEAD: u64 = 4 * 1024;
+
 #[derive(Debug)]
 pub struct ReadResult {
     pub entry: LogEntry,
@@ -50,18 +65,29 @@ pub struct ReadResult {
 }
 
 impl<ByteType> OffsetLog<ByteType> {
+    /// Opens (or creates) the log file at `path` for reading and writing.
+    ///
+    /// Takes an exclusive advisory lock on the file, and fails with
+    /// `FlumeLogError::LogLocked` if another handle already has it open.
     pub fn new<P: AsRef<Path>>(path: P) -> Result<OffsetLog<ByteType>, Error> {
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(&path)?;
+        lock_exclusive(&file, path.as_ref())?;
 
         OffsetLog::from_file(file)
     }
 
+    /// Opens the log file at `path` for reading.
+    ///
+    /// Takes a shared advisory lock on the file, so any number of readers can
+    /// coexist, but not alongside a writer opened with [`OffsetLog::new`].
     pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<OffsetLog<ByteType>, Error> {
         let file = OpenOptions::new().read(true).open(&path)?;
+        lock_shared(&file, path.as_ref())?;
 
         OffsetLog::from_file(file)
     }
@@ -70,7 +96,11 @@ impl<ByteType> OffsetLog<ByteType> {
         let file_length = file.seek(SeekFrom::End(0))?;
 
         let last_offset = if file_length > 0 {
-            let frame = read_prev_frame::<ByteType, _>(file_length, |b, o| file.read_at(b, o))?;
+            let frame = read_prev_frame::<ByteType, _>(file_length, |b, o| file.read_at(b, o))
+                .map_err(|source| Error::OffsetLog {
+                    offset: file_length,
+                    source,
+                })?;
             Some(frame.offset)
         } else {
             None
@@ -79,6 +109,7 @@ impl<ByteType> OffsetLog<ByteType> {
         Ok(OffsetLog {
             file,
             end_of_file: file_length,
+            committed_end: Arc::new(AtomicU64::new(file_length)),
             last_offset,
             tmp_buffer: BytesMut::new(),
             byte_type: PhantomData,
@@ -89,7 +120,10 @@ impl<ByteType> OffsetLog<ByteType> {
         self.end_of_file
     }
 
+    /// Reads the entry at `offset`, failing with `FlumeLogError::SequenceNotFound`
+    /// if `offset` isn't the start of an entry.
     pub fn read(&self, offset: u64) -> Result<ReadResult, Error> {
+        check_entry_start::<ByteType, _>(offset, self.end(), &self.file)?;
         read_next::<ByteType, _>(offset, &self.file)
     }
 
@@ -101,17 +135,37 @@ impl<ByteType> OffsetLog<ByteType> {
             //Maybe there's a more functional way of doing this. Kinda mixing functional and
             //imperative.
             offsets.push(offset);
-            encode::<ByteType>(offset, &buff.as_ref(), &mut bytes)
+            encode::<ByteType>(offset, buff.as_ref(), &mut bytes)
         })?;
 
-        offsets.last().map(|o| self.last_offset = Some(*o));
+        if let Some(o) = offsets.last() {
+            self.last_offset = Some(*o);
+        }
 
         self.file.write_at(&bytes, self.end_of_file)?;
-        self.end_of_file = new_end;
+        self.set_end(new_end);
 
         Ok(offsets)
     }
 
+    /// Returns a read-only handle to this log that can be shared across threads.
+    ///
+    /// The reader only observes entries that have been completely written by
+    /// this log; entries appended later become visible to the reader as soon
+    /// as the append returns.
+    pub fn reader(&self) -> Result<OffsetLogReader<ByteType>, Error> {
+        Ok(OffsetLogReader {
+            file: Arc::new(self.file.try_clone()?),
+            end: self.committed_end.clone(),
+            byte_type: PhantomData,
+        })
+    }
+
+    fn set_end(&mut self, new_end: u64) {
+        self.end_of_file = new_end;
+        self.committed_end.store(new_end, Ordering::Release);
+    }
+
     pub fn iter(&self) -> Forward<OffsetLogIter<ByteType>> {
         OffsetLogIter::new(self.file.try_clone().unwrap()).forward_owned()
     }
@@ -145,29 +199,132 @@ impl<ByteType> FlumeLog for OffsetLog<ByteType> {
         let new_end = encode::<ByteType>(offset, buff, &mut self.tmp_buffer)?;
         self.file.write_at(&self.tmp_buffer, offset)?;
 
-        self.end_of_file = new_end;
+        self.set_end(new_end);
         self.last_offset = Some(offset);
         Ok(offset)
     }
 
-    fn clear(&mut self, _seq_num: u64) {
-        unimplemented!();
+    /// Overwrites the entry's data with zeroes, leaving its framing intact.
+    fn clear(&mut self, seq_num: u64) -> Result<(), Error> {
+        let r = self.read(seq_num)?;
+        let zeroes = vec![0; r.entry.data.len()];
+        self.file
+            .write_at(&zeroes, seq_num + size_of::<u32>() as u64)?;
+        Ok(())
+    }
+
+    /// Sorts `seqs` and reads entries that are close together in the file
+    /// with a single read, instead of two reads per entry.
+    fn get_many(&self, seqs: &[Sequence]) -> Vec<Result<Vec<u8>, Error>> {
+        read_many::<ByteType, _>(seqs, self.end(), &self.file)
+            .into_iter()
+            .map(|r| r.map(|r| r.entry.data))
+            .collect()
+    }
+}
+
+impl<ByteType> NextSequence for OffsetLog<ByteType> {
+    fn next_sequence(&self, _len: usize) -> u64 {
+        self.end()
+    }
+}
+
+impl<ByteType> Overwrite for OffsetLog<ByteType> {
+    fn overwrite(&mut self, seq: u64, data: &[u8]) -> Result<(), Error> {
+        let r = self.read(seq)?;
+        check_overwrite_length(seq, &r.entry.data, data)?;
+        self.file.write_at(data, seq + size_of::<u32>() as u64)?;
+        Ok(())
+    }
+}
+
+/// A cloneable, `Send + Sync` read-only view of an [`OffsetLog`].
+///
+/// Created with [`OffsetLog::reader`]. Reads are bounded by the end offset
+/// most recently published by the writer, so a reader never sees a
+/// partially written entry.
+pub struct OffsetLogReader<ByteType> {
+    file: Arc<File>,
+    end: Arc<AtomicU64>,
+    byte_type: PhantomData<ByteType>,
+}
+
+impl<ByteType> Clone for OffsetLogReader<ByteType> {
+    fn clone(&self) -> Self {
+        OffsetLogReader {
+            file: self.file.clone(),
+            end: self.end.clone(),
+            byte_type: PhantomData,
+        }
+    }
+}
+
+impl<ByteType> OffsetLogReader<ByteType> {
+    /// The end of the committed part of the log.
+    pub fn end(&self) -> u64 {
+        self.end.load(Ordering::Acquire)
+    }
+
+    pub fn read(&self, offset: u64) -> Result<ReadResult, Error> {
+        check_entry_start::<ByteType, _>(offset, self.end(), self)?;
+        read_next::<ByteType, _>(offset, self)
+    }
+
+    pub fn get(&self, seq_num: u64) -> Result<Vec<u8>, Error> {
+        self.read(seq_num).map(|r| r.entry.data)
+    }
+
+    pub fn iter(&self) -> Forward<OffsetLogIter<ByteType, OffsetLogReader<ByteType>>> {
+        self.bidir_iter().forward_owned()
+    }
+
+    pub fn bidir_iter(&self) -> OffsetLogIter<ByteType, OffsetLogReader<ByteType>> {
+        self.bidir_iter_at_offset(0)
+    }
+
+    pub fn bidir_iter_at_offset(
+        &self,
+        offset: u64,
+    ) -> OffsetLogIter<ByteType, OffsetLogReader<ByteType>> {
+        OffsetLogIter::with_starting_offset(self.clone(), offset)
     }
 }
 
-pub struct OffsetLogIter<ByteType> {
-    reader: BufOffsetReader<File>,
+impl<ByteType> OffsetRead for OffsetLogReader<ByteType> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let end = self.end();
+        if offset >= end {
+            return Ok(0);
+        }
+        let len = std::cmp::min(buf.len() as u64, end - offset) as usize;
+        self.file.read_at(&mut buf[..len], offset)
+    }
+}
+
+impl<ByteType> IterAtOffset<Forward<OffsetLogIter<ByteType, OffsetLogReader<ByteType>>>>
+    for OffsetLogReader<ByteType>
+{
+    fn iter_at_offset(
+        &self,
+        offset: u64,
+    ) -> Forward<OffsetLogIter<ByteType, OffsetLogReader<ByteType>>> {
+        self.bidir_iter_at_offset(offset).forward_owned()
+    }
+}
+
+pub struct OffsetLogIter<ByteType, R: OffsetRead = File> {
+    reader: BufOffsetReader<R>,
     current: u64,
     next: u64,
     byte_type: PhantomData<ByteType>,
 }
 
-impl<ByteType> OffsetLogIter<ByteType> {
-    pub fn new(file: File) -> OffsetLogIter<ByteType> {
+impl<ByteType, R: OffsetRead> OffsetLogIter<ByteType, R> {
+    pub fn new(file: R) -> OffsetLogIter<ByteType, R> {
         OffsetLogIter::with_starting_offset(file, 0)
     }
 
-    pub fn with_starting_offset(file: File, offset: u64) -> OffsetLogIter<ByteType> {
+    pub fn with_starting_offset(file: R, offset: u64) -> OffsetLogIter<ByteType, R> {
         OffsetLogIter {
             reader: BufOffsetReader::new(file),
             current: offset,
@@ -183,19 +340,19 @@ impl<ByteType> IterAtOffset<Forward<OffsetLogIter<ByteType>>> for OffsetLog<Byte
     }
 }
 
-impl<ByteType> BidirIterator for OffsetLogIter<ByteType> {
+impl<ByteType, R: OffsetRead> BidirIterator for OffsetLogIter<ByteType, R> {
     type Item = LogEntry;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.current = self.next;
-        let r = read_next_mut::<u32, _>(self.current, &mut self.reader).ok()?;
+        let r = read_next_mut::<ByteType, _>(self.current, &mut self.reader).ok()?;
         self.next = r.next;
         Some(r.entry)
     }
 
     fn prev(&mut self) -> Option<Self::Item> {
         self.next = self.current;
-        let r = read_prev_mut::<u32, _>(self.current, &mut self.reader).ok()?;
+        let r = read_prev_mut::<ByteType, _>(self.current, &mut self.reader).ok()?;
         self.current = r.entry.offset;
         Some(r.entry)
     }
@@ -212,7 +369,7 @@ pub fn encode<T>(offset: u64, item: &[u8], dest: &mut BytesMut) -> Result<u64, E
     let chunk_size = size_of_framing_bytes::<T>() + item.len();
     dest.reserve(chunk_size);
     dest.put_u32(item.len() as u32);
-    dest.put_slice(&item);
+    dest.put_slice(item);
     dest.put_u32(item.len() as u32);
     let next_offset = offset + chunk_size as u64;
 
@@ -221,13 +378,22 @@ pub fn encode<T>(offset: u64, item: &[u8], dest: &mut BytesMut) -> Result<u64, E
 }
 
 pub fn validate_entry<T>(offset: u64, data_size: usize, rest: &[u8]) -> Result<u64, Error> {
+    validate_entry_impl::<T>(offset, data_size, rest)
+        .map_err(|source| Error::OffsetLog { offset, source })
+}
+
+fn validate_entry_impl<T>(
+    offset: u64,
+    data_size: usize,
+    rest: &[u8],
+) -> Result<u64, FlumeOffsetLogError> {
     if rest.len() != data_size + size_of_frame_tail::<T>() {
-        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {});
     }
 
     let sz = (&rest[data_size..]).read_u32::<BigEndian>()? as usize;
     if sz != data_size {
-        return Err(FlumeOffsetLogError::CorruptLogFile {}.into());
+        return Err(FlumeOffsetLogError::CorruptLogFile {});
     }
 
     let next =
@@ -236,11 +402,119 @@ pub fn validate_entry<T>(offset: u64, data_size: usize, rest: &[u8]) -> Result<u
     // `next` should be equal to the offset of the next entry
     // which may or may not be immediately following this one (I suppose)
     if next < offset + size_of::<u32>() as u64 + rest.len() as u64 {
-        return Err(FlumeOffsetLogError::CorruptLogFile {}.into());
+        return Err(FlumeOffsetLogError::CorruptLogFile {});
     }
     Ok(next)
 }
 
+/// Checks that `offset` is where an entry starts, in a log that ends at `end`.
+///
+/// Every entry ends with the offset of the entry after it, so an entry starts
+/// at `offset` if it's the first one, or the preceding bytes point to it.
+fn check_entry_start<ByteType, R: OffsetRead>(offset: u64, end: u64, r: &R) -> Result<(), Error> {
+    let not_found = || Err(FlumeLogError::SequenceNotFound { sequence: offset }.into());
+    if offset >= end {
+        return not_found();
+    }
+    if offset == 0 {
+        return Ok(());
+    }
+    if offset < size_of_framing_bytes::<ByteType>() as u64 {
+        return not_found();
+    }
+
+    let mut tmp = [0; size_of::<u64>()];
+    let next_size = size_of::<ByteType>();
+    let n = r.read_at(&mut tmp[..next_size], offset - next_size as u64)?;
+    if n < next_size {
+        return not_found();
+    }
+    let prev_next = (&tmp[..next_size]).read_uint::<BigEndian>(next_size)?;
+    if prev_next != offset {
+        return not_found();
+    }
+    Ok(())
+}
+
+/// Reads the entries at `offsets`, in order, reading each group of entries
+/// that are near each other in one go.
+fn read_many<ByteType, R: OffsetRead>(
+    offsets: &[u64],
+    end: u64,
+    r: &R,
+) -> Vec<Result<ReadResult, Error>> {
+    let mut order: Vec<usize> = (0..offsets.len()).collect();
+    order.sort_by_key(|i| offsets[*i]);
+
+    let mut results: Vec<Option<Result<ReadResult, Error>>> =
+        offsets.iter().map(|_| None).collect();
+    let mut rest = &order[..];
+    while let Some(first) = rest.first() {
+        let group_start = offsets[*first];
+        let len = rest
+            .windows(2)
+            .take_while(|w| {
+                offsets[w[1]] - offsets[w[0]] <= COALESCE_GAP
+                    && offsets[w[1]] - group_start <= MAX_COALESCED_READ
+            })
+            .count()
+            + 1;
+        let (group, remaining) = rest.split_at(len);
+        rest = remaining;
+
+        // Checking that an offset is the start of an entry reads the end of
+        // the entry before it.
+        let start = group_start.saturating_sub(size_of::<ByteType>() as u64);
+        let last = offsets[group[group.len() - 1]];
+        let cached = CachedRange::read(r, start.min(end), last.saturating_add(READ_AHEAD).min(end));
+        for i in group {
+            let offset = offsets[*i];
+            results[*i] = Some(
+                check_entry_start::<ByteType, _>(offset, end, &cached)
+                    .and_then(|_| read_next::<ByteType, _>(offset, &cached)),
+            );
+        }
+    }
+    results.into_iter().map(Option::unwrap).collect()
+}
+
+/// A range of a file read into memory. Reads that fall outside of it go to
+/// the file.
+struct CachedRange<'a, R> {
+    inner: &'a R,
+    start: u64,
+    bytes: Vec<u8>,
+}
+
+impl<'a, R: OffsetRead> CachedRange<'a, R> {
+    fn read(inner: &'a R, start: u64, end: u64) -> CachedRange<'a, R> {
+        let mut bytes = vec![0; (end - start) as usize];
+        // If the read fails, every read goes to the file, and fails there.
+        let n = inner.read_at(&mut bytes, start).unwrap_or(0);
+        bytes.truncate(n);
+        CachedRange {
+            inner,
+            start,
+            bytes,
+        }
+    }
+}
+
+impl<'a, R: OffsetRead> OffsetRead for CachedRange<'a, R> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let cached = offset
+            .checked_sub(self.start)
+            .and_then(|from| self.bytes.get(from as usize..)?.get(..buf.len()));
+        match cached {
+            Some(cached) => {
+                buf.copy_from_slice(cached);
+                Ok(buf.len())
+            }
+            None => self.inner.read_at(buf, offset),
+        }
+    }
+}
+
 pub fn read_next<ByteType, R: OffsetRead>(offset: u64, r: &R) -> Result<ReadResult, Error> {
     read_next_impl::<ByteType, _>(offset, |b, o| r.read_at(b, o))
 }
@@ -267,19 +541,21 @@ fn read_next_impl<ByteType, F>(offset: u64, mut read_at: F) -> Result<ReadResult
 where
     F: FnMut(&mut [u8], u64) -> io::Result<usize>,
 {
-    let frame = read_next_frame::<ByteType, _>(offset, &mut read_at)?;
-    read_entry::<ByteType, _>(&frame, &mut read_at)
+    read_next_frame(offset, &mut read_at)
+        .and_then(|frame| read_entry::<ByteType, _>(&frame, &mut read_at))
+        .map_err(|source| Error::OffsetLog { offset, source })
 }
 
 fn read_prev_impl<ByteType, F>(offset: u64, mut read_at: F) -> Result<ReadResult, Error>
 where
     F: FnMut(&mut [u8], u64) -> io::Result<usize>,
 {
-    let frame = read_prev_frame::<ByteType, _>(offset, &mut read_at)?;
-    read_entry::<ByteType, _>(&frame, &mut read_at)
+    read_prev_frame::<ByteType, _>(offset, &mut read_at)
+        .and_then(|frame| read_entry::<ByteType, _>(&frame, &mut read_at))
+        .map_err(|source| Error::OffsetLog { offset, source })
 }
 
-fn read_next_frame<ByteType, F>(offset: u64, read_at: &mut F) -> Result<Frame, Error>
+fn read_next_frame<F>(offset: u64, read_at: &mut F) -> Result<Frame, FlumeOffsetLogError>
 where
     F: FnMut(&mut [u8], u64) -> io::Result<usize>,
 {
@@ -290,14 +566,14 @@ where
     let mut head_bytes = [0; HEAD_SIZE];
     let n = read_at(&mut head_bytes, offset)?;
     if n < HEAD_SIZE {
-        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {});
     }
 
     let data_size = (&head_bytes[..]).read_u32::<BigEndian>()? as usize;
     Ok(Frame { offset, data_size })
 }
 
-fn read_prev_frame<ByteType, F>(offset: u64, mut read_at: F) -> Result<Frame, Error>
+fn read_prev_frame<ByteType, F>(offset: u64, mut read_at: F) -> Result<Frame, FlumeOffsetLogError>
 where
     F: FnMut(&mut [u8], u64) -> io::Result<usize>,
 {
@@ -306,17 +582,17 @@ where
     // big enough, assuming ByteType isn't bigger than a u64
     let mut tmp = [0; size_of::<u32>() + size_of::<u64>()];
     if tmp.len() as u64 > offset {
-        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {});
     }
 
     let n = read_at(&mut tmp[..tail_size], offset - tail_size as u64)?;
     if n < tail_size {
-        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {});
     }
 
     let data_size = (&tmp[..]).read_u32::<BigEndian>()? as usize;
     if (data_size as u64) > offset {
-        return Err(FlumeOffsetLogError::CorruptLogFile {}.into());
+        return Err(FlumeOffsetLogError::CorruptLogFile {});
     }
 
     let data_start = offset - tail_size as u64 - data_size as u64;
@@ -327,7 +603,10 @@ where
     })
 }
 
-fn read_entry<ByteType, F>(frame: &Frame, read_at: &mut F) -> Result<ReadResult, Error>
+fn read_entry<ByteType, F>(
+    frame: &Frame,
+    read_at: &mut F,
+) -> Result<ReadResult, FlumeOffsetLogError>
 where
     F: FnMut(&mut [u8], u64) -> io::Result<usize>,
 {
@@ -339,10 +618,10 @@ where
 
     let n = read_at(&mut buf, frame.data_start())?;
     if n < to_read {
-        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {});
     }
 
-    let next = validate_entry::<ByteType>(frame.offset, frame.data_size, &buf)?;
+    let next = validate_entry_impl::<ByteType>(frame.offset, frame.data_size, &buf)?;
 
     // Chop the tail off of buf, so it only contains the entry data.
     buf.truncate(frame.data_size);
@@ -366,12 +645,21 @@ mod test {
     use serde_json::{from_slice, Value};
 
     extern crate tempfile;
-    use self::tempfile::tempfile;
+    use self::tempfile::{tempdir, tempfile, TempDir};
+    use std::path::PathBuf;
 
     fn temp_offset_log() -> OffsetLog<u32> {
         OffsetLog::<u32>::from_file(tempfile().unwrap()).unwrap()
     }
 
+    // Opening a log for writing locks it, so tests that do so work on a copy.
+    fn copy_of_test_log() -> (TempDir, PathBuf) {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.offset");
+        std::fs::copy("./db/test.offset", &path).unwrap();
+        (dir, path)
+    }
+
     #[test]
     fn simple_encode() {
         let to_encode = vec![1, 2, 3, 4];
@@ -532,9 +820,60 @@ mod test {
         assert!(r.is_err());
     }
 
+    #[test]
+    fn errors_carry_offset() {
+        let bytes: &[u8] = &[
+            0, 0, 0, 1, 7, 0, 0, 0, 1, 0, 0, 0, 13, 0, 0, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 9,
+            0, 0, 0, 33,
+        ];
+        assert_eq!(read_next::<u32, _>(0, &bytes).unwrap().next, 13);
+
+        match read_next::<u32, _>(13, &bytes) {
+            Err(Error::OffsetLog {
+                offset: 13,
+                source: FlumeOffsetLogError::CorruptLogFile {},
+            }) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        let err = read_next::<u32, _>(13, &bytes).unwrap_err();
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn get_missing_sequence() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let not_found = |r: Result<Vec<u8>, Error>| {
+            matches!(r, Err(Error::Log(FlumeLogError::SequenceNotFound { .. })))
+        };
+
+        assert!(not_found(log.get(0)));
+
+        let a = log.append(b"abcdefgh")?;
+        let b = log.append(b"ijklmnop")?;
+        assert_eq!(log.get(a)?, b"abcdefgh");
+        assert_eq!(log.get(b)?, b"ijklmnop");
+
+        // Past the end
+        assert!(not_found(log.get(log.end())));
+        assert!(not_found(log.get(log.end() + 100)));
+
+        // In the middle of an entry
+        for offset in (a + 1)..b {
+            assert!(not_found(log.get(offset)), "offset {}", offset);
+        }
+        assert!(not_found(log.get(b + 3)));
+
+        let reader = log.reader()?;
+        assert!(not_found(reader.get(b + 3)));
+        assert!(not_found(reader.get(log.end())));
+        Ok(())
+    }
+
     #[test]
     fn read_from_a_file() {
-        let log = OffsetLog::<u32>::new("./db/test.offset").unwrap();
+        let (_dir, path) = copy_of_test_log();
+        let log = OffsetLog::<u32>::new(&path).unwrap();
         assert_eq!(log.latest(), Some(207));
 
         let result = log
@@ -566,6 +905,41 @@ mod test {
         assert!(log.append(&[1, 2, 3, 4]).is_err());
     }
 
+    #[test]
+    fn writer_locks_out_other_handles() -> Result<(), Error> {
+        let (_dir, path) = copy_of_test_log();
+
+        let log = OffsetLog::<u32>::new(&path)?;
+        for result in [
+            OffsetLog::<u32>::new(&path),
+            OffsetLog::<u32>::open_read_only(&path),
+        ] {
+            match result {
+                Err(Error::Log(FlumeLogError::LogLocked { .. })) => {}
+                _ => panic!("expected LogLocked"),
+            }
+        }
+
+        drop(log);
+        OffsetLog::<u32>::new(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn readers_share_lock() -> Result<(), Error> {
+        let (_dir, path) = copy_of_test_log();
+
+        let a = OffsetLog::<u32>::open_read_only(&path)?;
+        let b = OffsetLog::<u32>::open_read_only(&path)?;
+        assert_eq!(a.latest(), b.latest());
+        assert!(OffsetLog::<u32>::new(&path).is_err());
+
+        drop(a);
+        drop(b);
+        OffsetLog::<u32>::new(&path)?;
+        Ok(())
+    }
+
     #[test]
     fn write_to_a_file() -> Result<(), Error> {
         let test_vec = b"{\"value\": 1}";
@@ -592,11 +966,7 @@ mod test {
     fn batch_write_to_a_file() -> Result<(), Error> {
         let test_vec: &[u8] = b"{\"value\": 1}";
 
-        let mut test_vecs = Vec::new();
-
-        for _ in 0..100 {
-            test_vecs.push(test_vec);
-        }
+        let test_vecs = vec![test_vec; 100];
 
         let mut offset_log = temp_offset_log();
         let result = offset_log
@@ -612,10 +982,7 @@ mod test {
             })
             .and_then(|val| from_slice(&val).map_err(|err| err.into()))
             .map(|val: Value| match val["value"] {
-                Value::Number(ref num) => {
-                    let result = num.as_u64().unwrap();
-                    result
-                }
+                Value::Number(ref num) => num.as_u64().unwrap(),
                 _ => panic!(),
             })
             .unwrap();
@@ -627,7 +994,7 @@ mod test {
     fn arbitrary_read_and_write_to_a_file() -> Result<(), Error> {
         let mut offset_log = temp_offset_log();
 
-        let data_to_write = vec![b"{\"value\": 1}", b"{\"value\": 2}", b"{\"value\": 3}"];
+        let data_to_write = [b"{\"value\": 1}", b"{\"value\": 2}", b"{\"value\": 3}"];
 
         let seqs: Vec<u64> = data_to_write
             .iter()
@@ -640,10 +1007,7 @@ mod test {
             .map(|seq| offset_log.get(*seq).unwrap())
             .map(|val| from_slice(&val).unwrap())
             .map(|val: Value| match val["value"] {
-                Value::Number(ref num) => {
-                    let result = num.as_u64().unwrap();
-                    result
-                }
+                Value::Number(ref num) => num.as_u64().unwrap(),
                 _ => panic!(),
             })
             .sum();
@@ -654,7 +1018,8 @@ mod test {
 
     #[test]
     fn offset_log_as_iter() {
-        let log = OffsetLog::<u32>::new("./db/test.offset").unwrap();
+        let (_dir, path) = copy_of_test_log();
+        let log = OffsetLog::<u32>::new(&path).unwrap();
 
         let sum: u64 = log
             .iter()
@@ -662,10 +1027,7 @@ mod test {
             .map(|val| val.data)
             .map(|val| from_slice(&val).unwrap())
             .map(|val: Value| match val["value"] {
-                Value::Number(ref num) => {
-                    let result = num.as_u64().unwrap();
-                    result
-                }
+                Value::Number(ref num) => num.as_u64().unwrap(),
                 _ => panic!(),
             })
             .sum();
@@ -730,4 +1092,152 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn reader_sees_committed_entries() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        log.append(b"abc")?;
+
+        let reader = log.reader()?;
+        assert_eq!(reader.end(), log.end());
+        assert_eq!(reader.get(0)?, b"abc");
+
+        let mut iter = reader.bidir_iter();
+        assert_eq!(iter.next().unwrap().data, b"abc");
+        assert!(iter.next().is_none());
+
+        let offset = log.append(b"def")?;
+        assert_eq!(reader.end(), log.end());
+        assert_eq!(reader.get(offset)?, b"def");
+        assert_eq!(iter.next().unwrap().data, b"def");
+        assert!(iter.next().is_none());
+
+        let offsets: Vec<u64> = reader.iter().map(|e| e.offset).collect();
+        assert_eq!(offsets, &[0, 15]);
+        Ok(())
+    }
+
+    #[test]
+    fn reader_ignores_uncommitted_bytes() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        log.append(b"abc")?;
+        let reader = log.reader()?;
+
+        // Simulate a write that is still in progress.
+        let mut buf = BytesMut::new();
+        encode::<u32>(log.end(), b"def", &mut buf)?;
+        log.file.write_at(&buf, log.end())?;
+
+        assert!(reader.read(log.end()).is_err());
+        assert_eq!(reader.iter().count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn concurrent_readers_and_writer() -> Result<(), Error> {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+        use std::thread;
+
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<OffsetLogReader<u32>>();
+
+        const NUM_ENTRIES: u64 = 2000;
+
+        let mut log = temp_offset_log();
+        let reader = log.reader()?;
+        let done = Arc::new(AtomicBool::new(false));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let reader = reader.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    let mut last_count = 0;
+                    loop {
+                        let finished = done.load(Ordering::Acquire);
+                        let entries: Vec<_> = reader.iter().collect();
+                        let end = reader.end();
+
+                        for (i, e) in entries.iter().enumerate() {
+                            let v: Value = from_slice(&e.data).unwrap();
+                            assert_eq!(v["value"], i as u64);
+                        }
+                        assert!(entries.len() >= last_count);
+                        assert!(entries.last().map_or(0, |e| e.offset) < end.max(1));
+                        last_count = entries.len();
+
+                        if finished {
+                            return last_count;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for i in 0..NUM_ENTRIES {
+            let data = format!("{{\"value\": {}}}", i);
+            if i % 3 == 0 {
+                log.append_batch(&[data.as_bytes()])?;
+            } else {
+                log.append(data.as_bytes())?;
+            }
+        }
+        done.store(true, Ordering::Release);
+
+        for handle in readers {
+            assert_eq!(handle.join().unwrap(), NUM_ENTRIES as usize);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn get_many_coalesces_reads() -> Result<(), Error> {
+        struct CountingReader<'a>(&'a [u8], std::cell::Cell<usize>);
+        impl OffsetRead for CountingReader<'_> {
+            fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+                self.1.set(self.1.get() + 1);
+                self.0.read_at(buf, offset)
+            }
+        }
+
+        let mut bytes = BytesMut::new();
+        let mut offsets = Vec::new();
+        let mut end = 0;
+        for i in 0..1000 {
+            offsets.push(end);
+            // Every hundredth entry is too big to be read ahead.
+            let len = if i % 100 == 0 { 10_000 } else { 30 };
+            end = encode::<u32>(end, &vec![(i % 256) as u8; len], &mut bytes)?;
+        }
+        let reader = CountingReader(&bytes, std::cell::Cell::new(0));
+
+        let mut wanted: Vec<u64> = offsets.iter().rev().step_by(3).cloned().collect();
+        wanted.push(offsets[10] + 1);
+        wanted.push(offsets[10]);
+        let results = read_many::<u32, _>(&wanted, end, &reader);
+        assert_eq!(results.len(), wanted.len());
+
+        for (offset, result) in wanted.iter().zip(&results) {
+            match offsets.binary_search(offset) {
+                Ok(i) => assert_eq!(result.as_ref().unwrap().entry.data[0], (i % 256) as u8),
+                Err(_) => assert!(result.is_err()),
+            }
+        }
+        // One read for each group, and one more for each big entry.
+        assert!(reader.1.get() < 10, "{} reads", reader.1.get());
+
+        // Sequences past the end of the log only fail their own read.
+        let log = temp_offset_log();
+        let results = log.get_many(&[end, 0]);
+        assert!(matches!(
+            results[0],
+            Err(Error::Log(FlumeLogError::SequenceNotFound { .. }))
+        ));
+        assert!(matches!(
+            results[1],
+            Err(Error::Log(FlumeLogError::SequenceNotFound { sequence: 0 }))
+        ));
+        Ok(())
+    }
 }
@@ -0,0 +1,785 @@
+//! Declarative queries over the JSON entries of a log, in the spirit of the
+//! JS `flumeview-query` and `map-filter-reduce` modules.
+//!
+//! A [`Query`] filters entries on values at JSON paths, sorts, limits, maps
+//! and reduces them. A [`QueryEngine`] runs queries against a log, using an
+//! [`IndexView`] on one of the filtered paths to find candidate entries when
+//! it has one, and scanning the whole log when it doesn't.
+//!
+//! ```
+//! use flumedb::query::*;
+//! use flumedb::{FlumeLog, IndexView, MemLog};
+//! use serde_json::json;
+//!
+//! let mut log = MemLog::new();
+//! log.append(br#"{"type": "post", "likes": 3}"#).unwrap();
+//! log.append(br#"{"type": "vote"}"#).unwrap();
+//! log.append(br#"{"type": "post", "likes": 5}"#).unwrap();
+//!
+//! let mut by_type = IndexView::new(1, |_, data| index_keys_at(data, &["type"]));
+//! by_type.catch_up(&log);
+//!
+//! let engine = QueryEngine::new(&log).with_index(&["type"], &by_type);
+//! let query = Query::new()
+//!     .filter(Filter::eq(&["type"], json!("post")))
+//!     .reduce(Reduce::Sum(path(&["likes"])));
+//!
+//! assert_eq!(engine.plan(&query), Plan::Index { path: path(&["type"]) });
+//! assert_eq!(engine.run(&query).unwrap(), vec![json!(8)]);
+//! ```
+
+use crate::flume_log::*;
+use crate::index_view::IndexView;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use serde_json::{Number, Value};
+use std::cmp::Ordering;
+use std::ops::Bound;
+
+/// A path into a JSON value: object keys, or array indexes written as numbers.
+pub type JsonPath = Vec<String>;
+
+/// Builds a [`JsonPath`] from its segments.
+pub fn path(segments: &[&str]) -> JsonPath {
+    segments.iter().map(|s| s.to_string()).collect()
+}
+
+/// The value at `path` in `value`, if there is one.
+pub fn value_at<'v>(value: &'v Value, path: &[String]) -> Option<&'v Value> {
+    path.iter().try_fold(value, |v, segment| match v {
+        Value::Object(map) => map.get(segment),
+        Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => None,
+    })
+}
+
+/// Compares two JSON values of the same kind. Values of different kinds, and
+/// arrays, objects and nulls, don't compare.
+pub fn compare_values(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Number(a), Value::Number(b)) => match (a.as_i64(), b.as_i64()) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        },
+        _ => None,
+    }
+}
+
+/// A condition on the JSON value of a log entry.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Filter {
+    /// The value at the path equals the given value.
+    Eq(JsonPath, Value),
+    /// The value at the path is greater than the given value.
+    Gt(JsonPath, Value),
+    /// The value at the path is greater than or equal to the given value.
+    Gte(JsonPath, Value),
+    /// The value at the path is less than the given value.
+    Lt(JsonPath, Value),
+    /// The value at the path is less than or equal to the given value.
+    Lte(JsonPath, Value),
+    /// The value at the path is a string starting with the given prefix.
+    Prefix(JsonPath, String),
+    /// There is a value at the path.
+    Exists(JsonPath),
+    /// Every one of the filters matches.
+    All(Vec<Filter>),
+    /// At least one of the filters matches.
+    Any(Vec<Filter>),
+    /// The filter doesn't match.
+    Not(Box<Filter>),
+}
+
+impl Filter {
+    pub fn eq(path: &[&str], value: Value) -> Filter {
+        Filter::Eq(self::path(path), value)
+    }
+
+    pub fn gt(path: &[&str], value: Value) -> Filter {
+        Filter::Gt(self::path(path), value)
+    }
+
+    pub fn gte(path: &[&str], value: Value) -> Filter {
+        Filter::Gte(self::path(path), value)
+    }
+
+    pub fn lt(path: &[&str], value: Value) -> Filter {
+        Filter::Lt(self::path(path), value)
+    }
+
+    pub fn lte(path: &[&str], value: Value) -> Filter {
+        Filter::Lte(self::path(path), value)
+    }
+
+    pub fn prefix(path: &[&str], prefix: &str) -> Filter {
+        Filter::Prefix(self::path(path), prefix.to_string())
+    }
+
+    pub fn exists(path: &[&str]) -> Filter {
+        Filter::Exists(self::path(path))
+    }
+
+    /// Whether `value` satisfies the filter.
+    pub fn matches(&self, value: &Value) -> bool {
+        let compare = |path: &JsonPath, other: &Value| {
+            value_at(value, path).and_then(|v| compare_values(v, other))
+        };
+        match self {
+            Filter::Eq(path, other) => value_at(value, path) == Some(other),
+            Filter::Gt(path, other) => compare(path, other) == Some(Ordering::Greater),
+            Filter::Gte(path, other) => compare(path, other).is_some_and(|o| o.is_ge()),
+            Filter::Lt(path, other) => compare(path, other) == Some(Ordering::Less),
+            Filter::Lte(path, other) => compare(path, other).is_some_and(|o| o.is_le()),
+            Filter::Prefix(path, prefix) => value_at(value, path)
+                .and_then(Value::as_str)
+                .is_some_and(|s| s.starts_with(prefix.as_str())),
+            Filter::Exists(path) => value_at(value, path).is_some(),
+            Filter::All(filters) => filters.iter().all(|f| f.matches(value)),
+            Filter::Any(filters) => filters.iter().any(|f| f.matches(value)),
+            Filter::Not(filter) => !filter.matches(value),
+        }
+    }
+
+    /// The filters that must all match for this one to, so that any of them
+    /// can be looked up in an index.
+    fn conjuncts(&self) -> Vec<&Filter> {
+        match self {
+            Filter::All(filters) => filters.iter().flat_map(Filter::conjuncts).collect(),
+            f => vec![f],
+        }
+    }
+}
+
+/// How a query combines its results into a single value.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reduce {
+    /// The number of results.
+    Count,
+    /// The sum of the numbers at the path in each result.
+    Sum(JsonPath),
+    /// The smallest value at the path in any result, or null.
+    Min(JsonPath),
+    /// The largest value at the path in any result, or null.
+    Max(JsonPath),
+}
+
+/// A declarative query, built up with its builder methods.
+///
+/// Running it filters the log's JSON entries (entries that aren't JSON are
+/// skipped), then sorts, reverses and limits them, maps each one to the
+/// value at a path, and finally reduces them to a single value.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    pub filter: Option<Filter>,
+    pub sort: Option<JsonPath>,
+    pub reverse: bool,
+    pub limit: Option<usize>,
+    pub map: Option<JsonPath>,
+    pub reduce: Option<Reduce>,
+}
+
+impl Query {
+    /// A query that returns every JSON entry in log order.
+    pub fn new() -> Query {
+        Query::default()
+    }
+
+    /// Adds a filter, which must match along with any added before it.
+    pub fn filter(mut self, filter: Filter) -> Query {
+        self.filter = Some(match self.filter.take() {
+            None => filter,
+            Some(Filter::All(mut filters)) => {
+                filters.push(filter);
+                Filter::All(filters)
+            }
+            Some(f) => Filter::All(vec![f, filter]),
+        });
+        self
+    }
+
+    /// Sorts results by the value at `path` rather than in log order.
+    /// Results without a comparable value there come last.
+    pub fn sort(mut self, path: &[&str]) -> Query {
+        self.sort = Some(self::path(path));
+        self
+    }
+
+    pub fn reverse(mut self) -> Query {
+        self.reverse = true;
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Query {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Replaces each result with the value at `path`, dropping results that
+    /// don't have one.
+    pub fn map(mut self, path: &[&str]) -> Query {
+        self.map = Some(self::path(path));
+        self
+    }
+
+    pub fn reduce(mut self, reduce: Reduce) -> Query {
+        self.reduce = Some(reduce);
+        self
+    }
+}
+
+/// A key in an [`IndexView`] that a [`QueryEngine`] can use, made from a JSON
+/// scalar. Keys of different kinds order by kind: nulls, then booleans, then
+/// numbers, then strings.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum IndexKey {
+    Null,
+    Bool(bool),
+    Num(Num),
+    Str(String),
+}
+
+/// A JSON number as an index key. Integers and floats share one order, by
+/// value, so `20` and `20.0` are the same key.
+///
+/// Integers too large for an `f64` to hold exactly share a key with their
+/// neighbours, so an index lookup can return entries that don't match; the
+/// [`QueryEngine`] checks its candidates against the filter anyway.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(from = "f64", into = "f64")]
+pub struct Num(f64);
+
+impl Num {
+    pub fn new(n: f64) -> Num {
+        // -0.0 equals 0.0 as a JSON value, so it needs to be the same key.
+        Num(if n == 0.0 { 0.0 } else { n })
+    }
+
+    pub fn get(self) -> f64 {
+        self.0
+    }
+}
+
+impl From<f64> for Num {
+    fn from(n: f64) -> Num {
+        Num::new(n)
+    }
+}
+
+impl From<Num> for f64 {
+    fn from(n: Num) -> f64 {
+        n.0
+    }
+}
+
+impl PartialEq for Num {
+    fn eq(&self, other: &Num) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Num {}
+
+impl PartialOrd for Num {
+    fn partial_cmp(&self, other: &Num) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Num {
+    fn cmp(&self, other: &Num) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl IndexKey {
+    /// The key for `value`, if it's a JSON scalar that can be indexed.
+    pub fn from_value(value: &Value) -> Option<IndexKey> {
+        match value {
+            Value::Null => Some(IndexKey::Null),
+            Value::Bool(b) => Some(IndexKey::Bool(*b)),
+            Value::Number(n) => n.as_f64().map(|n| IndexKey::Num(Num::new(n))),
+            Value::String(s) => Some(IndexKey::Str(s.clone())),
+            _ => None,
+        }
+    }
+
+    /// The bounds of the keys of the same kind as this one.
+    fn kind_bounds(&self) -> (Bound<IndexKey>, Bound<IndexKey>) {
+        use std::ops::Bound::*;
+        match self {
+            IndexKey::Null => (Included(IndexKey::Null), Included(IndexKey::Null)),
+            IndexKey::Bool(_) => (
+                Included(IndexKey::Bool(false)),
+                Included(IndexKey::Bool(true)),
+            ),
+            IndexKey::Num(_) => (
+                Included(IndexKey::Num(Num::new(f64::NEG_INFINITY))),
+                Included(IndexKey::Num(Num::new(f64::INFINITY))),
+            ),
+            IndexKey::Str(_) => (Included(IndexKey::Str(String::new())), Unbounded),
+        }
+    }
+}
+
+/// The keys an [`IndexView`] used by a [`QueryEngine`] should have for the
+/// JSON entry `data`: the value at `path`, or each of its items if it's an
+/// array. Use it to write the index's key extractor.
+pub fn index_keys_at(data: &[u8], path: &[&str]) -> Vec<IndexKey> {
+    let value: Value = match serde_json::from_slice(data) {
+        Ok(value) => value,
+        Err(_) => return Vec::new(),
+    };
+    match value_at(&value, &self::path(path)) {
+        Some(Value::Array(items)) => items.iter().filter_map(IndexKey::from_value).collect(),
+        Some(v) => IndexKey::from_value(v).into_iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// How a [`QueryEngine`] finds the entries a query might match.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Plan {
+    /// Read every entry in the log.
+    Scan,
+    /// Look up candidates in the index on `path`, then read the entries the
+    /// index hasn't caught up with yet.
+    Index { path: JsonPath },
+}
+
+/// Runs [`Query`]s against a log and the indexes registered with it.
+pub struct QueryEngine<'a, L> {
+    log: &'a L,
+    indexes: Vec<(JsonPath, &'a IndexView<IndexKey>)>,
+}
+
+impl<'a, L> QueryEngine<'a, L> {
+    pub fn new(log: &'a L) -> QueryEngine<'a, L> {
+        QueryEngine {
+            log,
+            indexes: Vec::new(),
+        }
+    }
+
+    /// Registers `index`, whose keys are the values at `path` in each entry
+    /// (as extracted by [`index_keys_at`]).
+    pub fn with_index(
+        mut self,
+        path: &[&str],
+        index: &'a IndexView<IndexKey>,
+    ) -> QueryEngine<'a, L> {
+        self.indexes.push((self::path(path), index));
+        self
+    }
+
+    /// How the engine would find the entries `query` might match.
+    pub fn plan(&self, query: &Query) -> Plan {
+        match self.index_lookup(query) {
+            Some((path, _, _)) => Plan::Index { path: path.clone() },
+            None => Plan::Scan,
+        }
+    }
+
+    /// Runs `query`, returning its results, or a single value if it has a reduce.
+    pub fn run<I>(&self, query: &Query) -> Result<Vec<Value>, Error>
+    where
+        L: FlumeLog + IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        let matches = |value: &Value| query.filter.as_ref().is_none_or(|f| f.matches(value));
+        let mut rows = Vec::new();
+
+        // Entries the index hasn't seen yet are scanned, as the whole log is
+        // when there's no index to use.
+        let since = match self.index_lookup(query) {
+            Some((_, index, seqs)) => {
+                for seq in seqs {
+                    if let Some(value) = parse(&self.log.get(seq)?) {
+                        if matches(&value) {
+                            rows.push((seq, value));
+                        }
+                    }
+                }
+                index.since()
+            }
+            None => None,
+        };
+        let skip = if since.is_some() { 1 } else { 0 };
+        for entry in self.log.iter_at_offset(since.unwrap_or(0)).skip(skip) {
+            if let Some(value) = parse(&entry.data) {
+                if matches(&value) {
+                    rows.push((entry.offset, value));
+                }
+            }
+        }
+
+        rows.sort_by_key(|(seq, _)| *seq);
+        let mut values: Vec<Value> = rows.into_iter().map(|(_, value)| value).collect();
+        if let Some(path) = &query.sort {
+            values.sort_by(|a, b| compare_at(a, b, path));
+        }
+        if query.reverse {
+            values.reverse();
+        }
+        if let Some(limit) = query.limit {
+            values.truncate(limit);
+        }
+        if let Some(path) = &query.map {
+            values = values
+                .iter()
+                .filter_map(|v| value_at(v, path).cloned())
+                .collect();
+        }
+        Ok(match &query.reduce {
+            Some(reduce) => vec![reduce_values(&values, reduce)],
+            None => values,
+        })
+    }
+
+    /// The first index on a path the query's filter requires a value at,
+    /// with the sequences it holds for the required values.
+    fn index_lookup<'q>(
+        &self,
+        query: &'q Query,
+    ) -> Option<(&'q JsonPath, &'a IndexView<IndexKey>, Vec<Sequence>)> {
+        let filter = query.filter.as_ref()?;
+        filter.conjuncts().into_iter().find_map(|f| {
+            let (path, seqs) = match f {
+                Filter::Eq(path, v) => {
+                    let key = IndexKey::from_value(v)?;
+                    (path, self.index_on(path)?.get(&key))
+                }
+                Filter::Gt(path, v) => self.index_range(path, v, |k| (Bound::Excluded(k), None))?,
+                Filter::Gte(path, v) => {
+                    self.index_range(path, v, |k| (Bound::Included(k), None))?
+                }
+                Filter::Lt(path, v) => {
+                    self.index_range(path, v, |k| (Bound::Unbounded, Some(Bound::Excluded(k))))?
+                }
+                Filter::Lte(path, v) => {
+                    self.index_range(path, v, |k| (Bound::Unbounded, Some(Bound::Included(k))))?
+                }
+                Filter::Prefix(path, prefix) => {
+                    let index = self.index_on(path)?;
+                    let lower = Bound::Included(IndexKey::Str(prefix.clone()));
+                    let seqs = index
+                        .range((lower, Bound::Unbounded))
+                        .take_while(|(k, _)| match k {
+                            IndexKey::Str(s) => s.starts_with(prefix.as_str()),
+                            _ => false,
+                        })
+                        .map(|(_, seq)| seq)
+                        .collect();
+                    (path, seqs)
+                }
+                _ => return None,
+            };
+            Some((path, self.index_on(path)?, seqs))
+        })
+    }
+
+    /// Looks up the keys of the same kind as `value` within the bounds that
+    /// `bounds` makes from its key. A missing upper bound means the end of
+    /// that kind of key; an unbounded lower bound the start of it.
+    ///
+    /// Excluded bounds are looked up as included ones, since numbers that
+    /// differ can share a [`Num`] key; the filter drops the extra candidates.
+    fn index_range<'f, F>(
+        &self,
+        path: &'f JsonPath,
+        value: &Value,
+        bounds: F,
+    ) -> Option<(&'f JsonPath, Vec<Sequence>)>
+    where
+        F: Fn(IndexKey) -> (Bound<IndexKey>, Option<Bound<IndexKey>>),
+    {
+        let index = self.index_on(path)?;
+        let key = IndexKey::from_value(value)?;
+        let (kind_lower, kind_upper) = key.kind_bounds();
+        let (lower, upper) = bounds(key);
+        let lower = match lower {
+            Bound::Unbounded => kind_lower,
+            b => inclusive(b),
+        };
+        let upper = upper.map(inclusive).unwrap_or(kind_upper);
+        let mut seqs: Vec<Sequence> = index.range((lower, upper)).map(|(_, seq)| seq).collect();
+        seqs.sort_unstable();
+        seqs.dedup();
+        Some((path, seqs))
+    }
+
+    fn index_on(&self, path: &JsonPath) -> Option<&'a IndexView<IndexKey>> {
+        self.indexes
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, index)| *index)
+    }
+}
+
+fn parse(data: &[u8]) -> Option<Value> {
+    serde_json::from_slice(data).ok()
+}
+
+fn inclusive<T>(bound: Bound<T>) -> Bound<T> {
+    match bound {
+        Bound::Excluded(k) => Bound::Included(k),
+        b => b,
+    }
+}
+
+fn compare_at(a: &Value, b: &Value, path: &[String]) -> Ordering {
+    match (value_at(a, path), value_at(b, path)) {
+        (Some(a), Some(b)) => compare_values(a, b).unwrap_or(Ordering::Equal),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+fn values_at<'v>(values: &'v [Value], path: &'v [String]) -> impl Iterator<Item = &'v Value> {
+    values.iter().filter_map(move |v| value_at(v, path))
+}
+
+fn reduce_values(values: &[Value], reduce: &Reduce) -> Value {
+    let extreme = |path: &JsonPath, keep: Ordering| {
+        values_at(values, path)
+            .fold(None, |best: Option<&Value>, v| match best {
+                Some(b) if compare_values(v, b) != Some(keep) => Some(b),
+                _ => Some(v),
+            })
+            .cloned()
+            .unwrap_or(Value::Null)
+    };
+    match reduce {
+        Reduce::Count => Value::from(values.len()),
+        Reduce::Sum(path) => {
+            let numbers: Vec<&Number> = values_at(values, path)
+                .filter_map(Value::as_number)
+                .collect();
+            // Integers that overflow an i64 are summed as floats.
+            let sum = numbers
+                .iter()
+                .try_fold(0i64, |sum, n| sum.checked_add(n.as_i64()?));
+            match sum {
+                Some(sum) => Value::from(sum),
+                None => Value::from(numbers.iter().filter_map(|n| n.as_f64()).sum::<f64>()),
+            }
+        }
+        Reduce::Min(path) => extreme(path, Ordering::Less),
+        Reduce::Max(path) => extreme(path, Ordering::Greater),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::*;
+    use crate::index_view::IndexView;
+    use crate::mem_log::MemLog;
+    use crate::offset_log::OffsetLog;
+    use crate::query::*;
+    use serde_json::json;
+
+    fn posts() -> MemLog {
+        let mut log = MemLog::new();
+        for entry in &[
+            json!({"author": "@a", "type": "post", "ts": 30, "tags": ["rust"]}),
+            json!({"author": "@b", "type": "vote", "ts": 10}),
+            json!({"author": "@b", "type": "post", "ts": 20, "tags": ["rust", "ssb"]}),
+            json!({"author": "@c", "type": "contact", "ts": 40}),
+            json!({"author": "@a", "type": "post", "ts": 50}),
+        ] {
+            log.append(&serde_json::to_vec(entry).unwrap()).unwrap();
+        }
+        log.append(b"not json").unwrap();
+        log
+    }
+
+    fn index(
+        log: &MemLog,
+        extract: crate::index_view::KeyExtractor<IndexKey>,
+    ) -> IndexView<IndexKey> {
+        let mut index = IndexView::new(1, extract);
+        index.catch_up(log);
+        index
+    }
+
+    #[test]
+    fn filters() {
+        let v = json!({"a": {"b": [1, "x"]}, "n": 2.5, "s": "hello"});
+        assert!(Filter::eq(&["a", "b", "0"], json!(1)).matches(&v));
+        assert!(Filter::gt(&["n"], json!(2)).matches(&v));
+        assert!(Filter::lte(&["n"], json!(2.5)).matches(&v));
+        assert!(!Filter::lt(&["s"], json!(3)).matches(&v));
+        assert!(Filter::prefix(&["s"], "he").matches(&v));
+        assert!(Filter::exists(&["a", "b", "1"]).matches(&v));
+        assert!(!Filter::exists(&["a", "c"]).matches(&v));
+        assert!(Filter::Any(vec![Filter::exists(&["x"]), Filter::exists(&["s"])]).matches(&v));
+        assert!(Filter::Not(Box::new(Filter::exists(&["x"]))).matches(&v));
+    }
+
+    #[test]
+    fn scan() -> Result<(), Error> {
+        let log = posts();
+        let engine = QueryEngine::new(&log);
+
+        let query = Query::new()
+            .filter(Filter::eq(&["type"], json!("post")))
+            .map(&["ts"]);
+        assert_eq!(engine.plan(&query), Plan::Scan);
+        assert_eq!(engine.run(&query)?, vec![json!(30), json!(20), json!(50)]);
+
+        let query = Query::new()
+            .filter(Filter::gte(&["ts"], json!(20)))
+            .sort(&["ts"])
+            .reverse()
+            .limit(2)
+            .map(&["author"]);
+        assert_eq!(engine.run(&query)?, vec![json!("@a"), json!("@c")]);
+
+        assert_eq!(
+            engine.run(&Query::new().reduce(Reduce::Count))?,
+            vec![json!(5)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reduces() -> Result<(), Error> {
+        let log = posts();
+        let engine = QueryEngine::new(&log);
+        let posts = Query::new().filter(Filter::eq(&["type"], json!("post")));
+
+        let run = |reduce| engine.run(&posts.clone().reduce(reduce));
+        assert_eq!(run(Reduce::Count)?, vec![json!(3)]);
+        assert_eq!(run(Reduce::Sum(path(&["ts"])))?, vec![json!(100)]);
+        assert_eq!(run(Reduce::Min(path(&["ts"])))?, vec![json!(20)]);
+        assert_eq!(run(Reduce::Max(path(&["author"])))?, vec![json!("@b")]);
+        assert_eq!(run(Reduce::Max(path(&["nope"])))?, vec![Value::Null]);
+
+        let mut log = MemLog::new();
+        log.append(format!(r#"{{"n": {}}}"#, i64::MAX - 1).as_bytes())?;
+        log.append(format!(r#"{{"n": {}}}"#, i64::MAX - 2).as_bytes())?;
+        let sum = QueryEngine::new(&log).run(&Query::new().reduce(Reduce::Sum(path(&["n"]))))?;
+        assert_eq!(sum, vec![json!(i64::MAX as f64 * 2.0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn indexed_queries_match_scans() -> Result<(), Error> {
+        let log = posts();
+        let by_type = index(&log, |_, data| index_keys_at(data, &["type"]));
+        let by_ts = index(&log, |_, data| index_keys_at(data, &["ts"]));
+        let by_tag = index(&log, |_, data| index_keys_at(data, &["tags"]));
+
+        let scan = QueryEngine::new(&log);
+        let indexed = QueryEngine::new(&log)
+            .with_index(&["type"], &by_type)
+            .with_index(&["ts"], &by_ts)
+            .with_index(&["tags"], &by_tag);
+
+        let queries = [
+            (Filter::eq(&["type"], json!("post")), "type"),
+            (Filter::gt(&["ts"], json!(20)), "ts"),
+            (Filter::gte(&["ts"], json!(20)), "ts"),
+            (Filter::lt(&["ts"], json!(30)), "ts"),
+            (Filter::lte(&["ts"], json!(30)), "ts"),
+            (Filter::lt(&["ts"], json!(i64::MIN)), "ts"),
+            (Filter::prefix(&["type"], "po"), "type"),
+            (
+                Filter::All(vec![
+                    Filter::exists(&["author"]),
+                    Filter::eq(&["ts"], json!(40)),
+                ]),
+                "ts",
+            ),
+        ];
+        for (filter, path) in queries {
+            let query = Query::new().filter(filter).map(&["ts"]);
+            assert_eq!(
+                indexed.plan(&query),
+                Plan::Index {
+                    path: self::path(&[path])
+                }
+            );
+            assert_eq!(indexed.run(&query)?, scan.run(&query)?, "{:?}", query);
+        }
+
+        // Non-conjunctive filters aren't planned.
+        for filter in [Filter::Any(vec![Filter::eq(&["type"], json!("post"))])] {
+            let query = Query::new().filter(filter);
+            assert_eq!(indexed.plan(&query), Plan::Scan);
+            assert_eq!(indexed.run(&query)?, scan.run(&query)?);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn indexes_ints_and_floats_together() -> Result<(), Error> {
+        let mut log = MemLog::new();
+        for ts in [
+            json!(10),
+            json!(20.0),
+            json!(20),
+            json!(25.5),
+            json!(-0.0),
+            json!(u64::MAX),
+            json!(9007199254740993_i64),
+            json!("20"),
+        ] {
+            log.append(&serde_json::to_vec(&json!({ "ts": ts })).unwrap())?;
+        }
+        let by_ts = index(&log, |_, data| index_keys_at(data, &["ts"]));
+
+        let scan = QueryEngine::new(&log);
+        let indexed = QueryEngine::new(&log).with_index(&["ts"], &by_ts);
+        for filter in [
+            Filter::eq(&["ts"], json!(20)),
+            Filter::eq(&["ts"], json!(20.0)),
+            Filter::eq(&["ts"], json!(0)),
+            Filter::gt(&["ts"], json!(20)),
+            Filter::gte(&["ts"], json!(20.5)),
+            Filter::lt(&["ts"], json!(25.5)),
+            Filter::lte(&["ts"], json!(20)),
+            Filter::gt(&["ts"], json!(9007199254740992_i64)),
+            Filter::lt(&["ts"], json!(9007199254740993_i64)),
+            Filter::gt(&["ts"], json!(i64::MAX)),
+        ] {
+            let query = Query::new().filter(filter).map(&["ts"]);
+            assert_eq!(
+                indexed.plan(&query),
+                Plan::Index {
+                    path: path(&["ts"])
+                }
+            );
+            assert_eq!(indexed.run(&query)?, scan.run(&query)?, "{:?}", query);
+        }
+
+        let query = Query::new().filter(Filter::gt(&["ts"], json!(20)));
+        assert_eq!(
+            indexed.run(&query.map(&["ts"]))?,
+            vec![json!(25.5), json!(u64::MAX), json!(9007199254740993_i64)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stale_index() -> Result<(), Error> {
+        let mut log = OffsetLog::<u32>::from_file(tempfile::tempfile()?)?;
+        log.append(br#"{"type": "post", "n": 1}"#)?;
+        let mut by_type = IndexView::new(1, |_, data| index_keys_at(data, &["type"]));
+        by_type.catch_up(&log);
+        log.append(br#"{"type": "post", "n": 2}"#)?;
+
+        let engine = QueryEngine::new(&log).with_index(&["type"], &by_type);
+        let query = Query::new()
+            .filter(Filter::eq(&["type"], json!("post")))
+            .map(&["n"]);
+        assert_eq!(engine.run(&query)?, vec![json!(1), json!(2)]);
+
+        let empty = IndexView::new(1, |_, data| index_keys_at(data, &["type"]));
+        let engine = QueryEngine::new(&log).with_index(&["type"], &empty);
+        assert_eq!(engine.run(&query)?, vec![json!(1), json!(2)]);
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+use crate::atomic_file::{read_if_exists, write_atomically};
+use crate::flume_log::*;
+use crate::flume_view::*;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// The function a [`ReduceView`] folds each log entry into its state with.
+pub type Reducer<S> = fn(S, Sequence, &[u8]) -> S;
+
+/// A view whose state is a reduction over every entry in the log, like the
+/// JS `flumeview-reduce`.
+///
+/// ```
+/// use flumedb::{FlumeView, ReduceView};
+///
+/// let mut count = ReduceView::new(1, 0u64, |n, _seq, _data| n + 1);
+/// count.append(0, b"abc");
+/// count.append(1, b"def");
+/// assert_eq!(*count.state(), 2);
+/// ```
+pub struct ReduceView<S> {
+    version: u32,
+    initial: S,
+    reduce: Reducer<S>,
+    // Only `None` while `reduce` is running.
+    state: Option<S>,
+    since: Option<Sequence>,
+    path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedState<S> {
+    version: u32,
+    seq: Option<Sequence>,
+    value: S,
+}
+
+impl<S: Clone> ReduceView<S> {
+    /// Creates a view that's kept in memory only.
+    ///
+    /// `version` identifies the reducer; bump it whenever `reduce` changes,
+    /// so that saved states are rebuilt rather than reused.
+    pub fn new(version: u32, initial: S, reduce: Reducer<S>) -> ReduceView<S> {
+        ReduceView {
+            version,
+            state: Some(initial.clone()),
+            initial,
+            reduce,
+            since: None,
+            path: None,
+        }
+    }
+
+    /// The current state of the view.
+    pub fn state(&self) -> &S {
+        self.state.as_ref().unwrap()
+    }
+
+    /// The sequence of the last entry reduced into the state, if any.
+    pub fn since(&self) -> Option<Sequence> {
+        self.since
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Discards the state, so the view can be rebuilt from the start of the log.
+    pub fn reset(&mut self) {
+        self.state = Some(self.initial.clone());
+        self.since = None;
+    }
+}
+
+impl<S: Clone + Serialize + DeserializeOwned> ReduceView<S> {
+    /// Creates a view whose state is saved to the file at `path`.
+    ///
+    /// If the file holds state saved by a view with the same `version`, the
+    /// view starts from that state; otherwise it starts from `initial`.
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        version: u32,
+        initial: S,
+        reduce: Reducer<S>,
+    ) -> Result<ReduceView<S>, Error> {
+        let mut view = ReduceView::new(version, initial, reduce);
+        view.path = Some(path.as_ref().to_owned());
+
+        if let Some(bytes) = read_if_exists(path.as_ref())? {
+            let saved: SavedState<S> = serde_json::from_slice(&bytes)?;
+            if saved.version == version {
+                view.state = Some(saved.value);
+                view.since = saved.seq;
+            }
+        }
+        Ok(view)
+    }
+
+    /// Writes the state to the view's file. Does nothing for in-memory views.
+    pub fn save(&self) -> Result<(), Error> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let saved = SavedState {
+            version: self.version,
+            seq: self.since,
+            value: self.state(),
+        };
+        write_atomically(path, &serde_json::to_vec(&saved)?)
+    }
+}
+
+impl<S: Clone> ReduceView<S> {
+    /// Starts the view from `state`, saved by the JS `flumeview-reduce`
+    /// version `js_version` of the same reducer, so that it only has to
+    /// reduce the entries appended since.
+    ///
+    /// Returns `false`, leaving the view as it was, if `state` was saved by
+    /// another version. Fails if `state.since` isn't an entry in `log`, the
+    /// log the JS view was built from, since the state can't be trusted then.
+    pub fn import_js<L: FlumeLog>(
+        &mut self,
+        state: JsReduceState<S>,
+        js_version: u32,
+        log: &L,
+    ) -> Result<bool, Error> {
+        if state.version != js_version {
+            return Ok(false);
+        }
+        state.validate(log)?;
+        self.state = Some(state.value);
+        self.since = state.since;
+        Ok(true)
+    }
+}
+
+/// The state of a JS `flumeview-reduce` view, as saved in the JSON file
+/// named after the view in the flume directory (`~/.ssb/flume/last.json`,
+/// for instance).
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsReduceState<S> {
+    pub version: u32,
+    /// The sequence of the last entry reduced into `value`, if any.
+    pub since: Option<Sequence>,
+    pub value: S,
+}
+
+#[derive(Deserialize)]
+struct SavedJsState<S> {
+    version: u32,
+    // Views that haven't seen any entries save a `seq` of -1.
+    seq: Option<i64>,
+    value: S,
+}
+
+impl<S: DeserializeOwned> JsReduceState<S> {
+    /// Reads the state saved in the file at `path`, or returns `None` if
+    /// there's no such file.
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Option<JsReduceState<S>>, Error> {
+        let bytes = match read_if_exists(path.as_ref())? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        let saved: SavedJsState<S> = serde_json::from_slice(&bytes)?;
+        Ok(Some(JsReduceState {
+            version: saved.version,
+            since: saved.seq.filter(|seq| *seq >= 0).map(|seq| seq as Sequence),
+            value: saved.value,
+        }))
+    }
+}
+
+impl<S> JsReduceState<S> {
+    /// Checks that `since` is the sequence of an entry in `log`, failing
+    /// with `FlumeLogError::SequenceNotFound` if it isn't.
+    pub fn validate<L: FlumeLog>(&self, log: &L) -> Result<(), Error> {
+        if let Some(since) = self.since {
+            log.get(since)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S> FlumeView for ReduceView<S> {
+    fn append(&mut self, seq: Sequence, item: &[u8]) {
+        let state = self.state.take().unwrap();
+        self.state = Some((self.reduce)(state, seq, item));
+        self.since = Some(seq);
+    }
+
+    /// The sequence of the last entry reduced into the state, or zero if none have been.
+    fn latest(&self) -> Sequence {
+        self.since.unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::*;
+    use crate::flume_view::*;
+    use crate::mem_log::MemLog;
+    use crate::offset_log::OffsetLog;
+    use crate::reduce_view::*;
+    use serde_json::Value;
+    use std::collections::BTreeMap;
+    use tempfile::tempdir;
+
+    type LatestByAuthor = BTreeMap<String, u64>;
+
+    fn latest_by_author(mut state: LatestByAuthor, _seq: Sequence, data: &[u8]) -> LatestByAuthor {
+        if let Ok(v) = serde_json::from_slice::<Value>(data) {
+            if let (Some(author), Some(seq)) = (v["author"].as_str(), v["sequence"].as_u64()) {
+                let latest = state.entry(author.to_string()).or_insert(seq);
+                *latest = seq.max(*latest);
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn count_and_bytes() {
+        let log = OffsetLog::<u32>::open_read_only("./db/test.offset").unwrap();
+
+        let mut count = ReduceView::new(1, 0u64, |n, _, _| n + 1);
+        let mut bytes = ReduceView::new(1, 0usize, |n, _, data| n + data.len());
+        for e in log.iter() {
+            count.append(e.offset, &e.data);
+            bytes.append(e.offset, &e.data);
+        }
+
+        assert_eq!(*count.state(), 10);
+        assert_eq!(
+            *bytes.state(),
+            log.iter().map(|e| e.data.len()).sum::<usize>()
+        );
+        assert_eq!(count.since(), log.latest());
+        assert_eq!(count.latest(), log.latest().unwrap());
+    }
+
+    #[test]
+    fn json_reduce() -> Result<(), Error> {
+        let mut log = MemLog::new();
+        log.append(br#"{"author": "@a", "sequence": 1}"#)?;
+        log.append(br#"{"author": "@b", "sequence": 1}"#)?;
+        log.append(br#"not json"#)?;
+        log.append(br#"{"author": "@a", "sequence": 2}"#)?;
+
+        let mut view = ReduceView::new(1, LatestByAuthor::new(), latest_by_author);
+        for e in log.iter() {
+            view.append(e.offset, &e.data);
+        }
+
+        assert_eq!(view.state()["@a"], 2);
+        assert_eq!(view.state()["@b"], 1);
+        assert_eq!(view.since(), Some(3));
+
+        view.reset();
+        assert!(view.state().is_empty());
+        assert_eq!(view.since(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn save_and_open() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("count.json");
+
+        let mut view = ReduceView::open(&path, 1, 0u64, |n, _, _| n + 1)?;
+        assert_eq!(view.since(), None);
+        view.append(0, b"a");
+        view.append(7, b"b");
+        view.save()?;
+
+        let mut view = ReduceView::open(&path, 1, 0u64, |n, _, _| n + 1)?;
+        assert_eq!(*view.state(), 2);
+        assert_eq!(view.since(), Some(7));
+        view.append(9, b"c");
+        assert_eq!(*view.state(), 3);
+
+        // A new version of the view starts over.
+        let view = ReduceView::open(&path, 2, 0u64, |n, _, _| n + 1)?;
+        assert_eq!(*view.state(), 0);
+        assert_eq!(view.since(), None);
+        Ok(())
+    }
+
+    #[test]
+    fn import_js_state() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("count.json");
+        let log = OffsetLog::<u32>::open_read_only("./db/test.offset")?;
+        let offsets: Vec<Sequence> = log.iter().map(|e| e.offset).collect();
+        let count = |n, _, _: &[u8]| n + 1;
+
+        assert_eq!(JsReduceState::<u64>::read(&path)?, None);
+
+        // The JS view had reduced the first five entries.
+        let js = format!(r#"{{"seq":{},"version":3,"value":5}}"#, offsets[4]);
+        std::fs::write(&path, js)?;
+        let state = JsReduceState::<u64>::read(&path)?.unwrap();
+        assert_eq!(state.since, Some(offsets[4]));
+
+        let mut view = ReduceView::new(1, 0u64, count);
+        assert!(!view.import_js(state.clone(), 2, &log)?);
+        assert_eq!(view.since(), None);
+        assert!(view.import_js(state, 3, &log)?);
+        for e in log.iter().skip_while(|e| e.offset <= offsets[4]) {
+            view.append(e.offset, &e.data);
+        }
+        assert_eq!(*view.state(), 10);
+
+        // A since that isn't an entry in the log can't be trusted.
+        let js = format!(r#"{{"seq":{},"version":3,"value":5}}"#, offsets[4] + 1);
+        std::fs::write(&path, js)?;
+        let state = JsReduceState::<u64>::read(&path)?.unwrap();
+        let mut view = ReduceView::new(1, 0u64, count);
+        assert!(matches!(
+            view.import_js(state, 3, &log),
+            Err(Error::Log(FlumeLogError::SequenceNotFound { .. }))
+        ));
+        assert_eq!(*view.state(), 0);
+
+        std::fs::write(&path, r#"{"seq":-1,"version":3,"value":0}"#)?;
+        let state = JsReduceState::<u64>::read(&path)?.unwrap();
+        assert_eq!(state.since, None);
+        assert!(view.import_js(state, 3, &log)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,348 @@
+use crate::flume_log::*;
+use crate::flume_view::*;
+use crate::index_view::IndexView;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::path::Path;
+
+// Bump this whenever `feed_key` changes.
+const VERSION: u32 = 2;
+
+/// An author's feed id and the sequence number of a message in that feed.
+pub type FeedKey = (String, u64);
+
+// A `FeedKey` and the key of the message there, so that a message appended
+// twice can be told apart from a fork.
+type IndexKey = (String, u64, String);
+
+/// Two or more messages in the log claiming the same place in a feed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fork {
+    pub author: String,
+    pub sequence: u64,
+    /// The log sequences of the messages, in log order.
+    pub entries: Vec<Sequence>,
+}
+
+/// An index of the SSB messages in the log by author and feed sequence
+/// number, like the JS `ssb-db` `clock` and `feed` views.
+///
+/// ```
+/// use flumedb::{FlumeLog, MemLog, SsbFeedView};
+///
+/// let mut log = MemLog::new();
+/// log.append(br#"{"key": "%1", "value": {"author": "@a", "sequence": 1}}"#).unwrap();
+/// log.append(br#"{"key": "%2", "value": {"author": "@b", "sequence": 1}}"#).unwrap();
+/// log.append(br#"{"key": "%3", "value": {"author": "@a", "sequence": 2}}"#).unwrap();
+///
+/// let mut feeds = SsbFeedView::new();
+/// feeds.catch_up(&log);
+/// assert_eq!(feeds.latest_sequence("@a"), Some(2));
+/// let history: Vec<_> = feeds.history("@a", 2).collect();
+/// assert_eq!(history, &[(2, 2)]);
+/// ```
+pub struct SsbFeedView {
+    index: IndexView<IndexKey>,
+    latest: BTreeMap<String, u64>,
+    forked: BTreeSet<FeedKey>,
+}
+
+impl SsbFeedView {
+    /// Creates a view that's kept in memory only.
+    pub fn new() -> SsbFeedView {
+        SsbFeedView::from_index(IndexView::new(VERSION, feed_key))
+    }
+
+    /// Creates a view that's saved to the file at `path`, starting from the
+    /// state saved there if there is one.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SsbFeedView, Error> {
+        Ok(SsbFeedView::from_index(IndexView::open(
+            path, VERSION, feed_key,
+        )?))
+    }
+
+    fn from_index(index: IndexView<IndexKey>) -> SsbFeedView {
+        let mut view = SsbFeedView {
+            index,
+            latest: BTreeMap::new(),
+            forked: BTreeSet::new(),
+        };
+        // Keys sort by place in the feed, then by message key, so a fork
+        // shows up as neighbours with different message keys.
+        let mut previous: Option<&IndexKey> = None;
+        for (key, _) in view.index.range(..) {
+            if let Some((author, sequence, msg_key)) = previous {
+                if (author, sequence) == (&key.0, &key.1) && msg_key != &key.2 {
+                    view.forked.insert((key.0.clone(), key.1));
+                }
+            }
+            view.latest.insert(key.0.clone(), key.1);
+            previous = Some(key);
+        }
+        view
+    }
+
+    /// Writes the view to its file. Does nothing for in-memory views.
+    pub fn save(&self) -> Result<(), Error> {
+        self.index.save()
+    }
+
+    /// Indexes the messages in `log` that haven't been indexed yet.
+    pub fn catch_up<L, I>(&mut self, log: &L)
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        let since = self.index.since();
+        catch_up(self, log, since);
+    }
+
+    /// The highest sequence number of any of `author`'s messages.
+    pub fn latest_sequence(&self, author: &str) -> Option<u64> {
+        self.latest.get(author).cloned()
+    }
+
+    /// The highest sequence number of each author's messages.
+    pub fn latest_sequences(&self) -> &BTreeMap<String, u64> {
+        &self.latest
+    }
+
+    /// The log sequence of message number `sequence` in `author`'s feed. If
+    /// the feed is forked there, this is the first of the messages.
+    pub fn get(&self, author: &str, sequence: u64) -> Option<Sequence> {
+        self.index
+            .range(feed_range(author, sequence, sequence))
+            .map(|(_, seq)| seq)
+            .min()
+    }
+
+    /// `author`'s messages from number `from` onwards, as pairs of feed
+    /// sequence number and log sequence.
+    pub fn history<'a>(
+        &'a self,
+        author: &str,
+        from: u64,
+    ) -> impl DoubleEndedIterator<Item = (u64, Sequence)> + 'a {
+        self.index
+            .range(feed_range(author, from, u64::MAX))
+            .map(|((_, sequence, _), seq)| (*sequence, seq))
+    }
+
+    /// Every place where a feed has more than one message.
+    ///
+    /// Messages are told apart by their keys, so appending the same message
+    /// to the log twice isn't a fork. A fork's entries include any such
+    /// copies.
+    pub fn forks(&self) -> Vec<Fork> {
+        self.forked
+            .iter()
+            .map(|(author, sequence)| {
+                let mut entries: Vec<Sequence> = self
+                    .index
+                    .range(feed_range(author, *sequence, *sequence))
+                    .map(|(_, seq)| seq)
+                    .collect();
+                entries.sort_unstable();
+                Fork {
+                    author: author.clone(),
+                    sequence: *sequence,
+                    entries,
+                }
+            })
+            .collect()
+    }
+
+    /// The sequence of the last entry the view has seen, if any.
+    pub fn since(&self) -> Option<Sequence> {
+        self.index.since()
+    }
+}
+
+impl Default for SsbFeedView {
+    fn default() -> SsbFeedView {
+        SsbFeedView::new()
+    }
+}
+
+impl FlumeView for SsbFeedView {
+    fn append(&mut self, seq: Sequence, item: &[u8]) {
+        for key in feed_key(seq, item) {
+            let forked = self
+                .index
+                .range(feed_range(&key.0, key.1, key.1))
+                .any(|((_, _, msg_key), _)| msg_key != &key.2);
+            if forked {
+                self.forked.insert((key.0.clone(), key.1));
+            }
+            let latest = self.latest.entry(key.0.clone()).or_insert(key.1);
+            *latest = key.1.max(*latest);
+        }
+        self.index.append(seq, item)
+    }
+
+    fn latest(&self) -> Sequence {
+        self.index.latest()
+    }
+}
+
+/// The author, sequence number and key of the SSB message `data`. Entries
+/// that aren't SSB messages have none of them.
+fn feed_key(_seq: Sequence, data: &[u8]) -> Vec<IndexKey> {
+    let msg: Value = match serde_json::from_slice(data) {
+        Ok(msg) => msg,
+        Err(_) => return Vec::new(),
+    };
+    let value = &msg["value"];
+    match (
+        value["author"].as_str(),
+        value["sequence"].as_u64(),
+        msg["key"].as_str(),
+    ) {
+        (Some(author), Some(sequence), Some(key)) => {
+            vec![(author.to_string(), sequence, key.to_string())]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// The keys of `author`'s messages numbered `from` to `to`, inclusive.
+fn feed_range(author: &str, from: u64, to: u64) -> (Bound<IndexKey>, Bound<IndexKey>) {
+    let lower = Bound::Included((author.to_string(), from, String::new()));
+    let upper = match to.checked_add(1) {
+        Some(next) => Bound::Excluded((author.to_string(), next, String::new())),
+        // The lowest key of the next author along.
+        None => Bound::Excluded((format!("{}\0", author), 0, String::new())),
+    };
+    (lower, upper)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::*;
+    use crate::go_offset_log::GoOffsetLog;
+    use crate::mem_log::MemLog;
+    use crate::ssb_feed_view::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    fn append_msg(log: &mut MemLog, author: &str, sequence: u64, text: &str) -> Sequence {
+        let msg = json!({
+            "key": format!("%{}.sha256", text),
+            "value": {"author": author, "sequence": sequence, "content": text},
+        });
+        log.append(&serde_json::to_vec(&msg).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn latest_and_history() {
+        let mut log = MemLog::new();
+        let a1 = append_msg(&mut log, "@a", 1, "a1");
+        let b1 = append_msg(&mut log, "@b", 1, "b1");
+        log.append(b"not a message").unwrap();
+        let a2 = append_msg(&mut log, "@a", 2, "a2");
+        let a3 = append_msg(&mut log, "@a", 3, "a3");
+
+        let mut view = SsbFeedView::new();
+        view.catch_up(&log);
+
+        assert_eq!(view.latest_sequence("@a"), Some(3));
+        assert_eq!(view.latest_sequence("@b"), Some(1));
+        assert_eq!(view.latest_sequence("@c"), None);
+        assert_eq!(view.latest_sequences().len(), 2);
+
+        assert_eq!(view.get("@a", 2), Some(a2));
+        assert_eq!(view.get("@b", 1), Some(b1));
+        assert_eq!(view.get("@b", 2), None);
+
+        let history: Vec<_> = view.history("@a", 0).collect();
+        assert_eq!(history, &[(1, a1), (2, a2), (3, a3)]);
+        let history: Vec<_> = view.history("@a", 2).rev().collect();
+        assert_eq!(history, &[(3, a3), (2, a2)]);
+        assert_eq!(view.history("@a", 4).count(), 0);
+        assert!(view.forks().is_empty());
+    }
+
+    #[test]
+    fn forks() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("feeds.cbor");
+
+        let mut log = MemLog::new();
+        append_msg(&mut log, "@a", 1, "a1");
+        let a2 = append_msg(&mut log, "@a", 2, "a2");
+        let mut view = SsbFeedView::open(&path)?;
+        view.catch_up(&log);
+        view.save()?;
+
+        let fork = append_msg(&mut log, "@a", 2, "a2-fork");
+        let mut view = SsbFeedView::open(&path)?;
+        assert!(view.forks().is_empty());
+        view.catch_up(&log);
+        let expected = vec![Fork {
+            author: "@a".to_string(),
+            sequence: 2,
+            entries: vec![a2, fork],
+        }];
+        assert_eq!(view.forks(), expected);
+        assert_eq!(view.get("@a", 2), Some(a2));
+        view.save()?;
+
+        // Forks and latest sequences survive a reload.
+        let view = SsbFeedView::open(&path)?;
+        assert_eq!(view.forks(), expected);
+        assert_eq!(view.latest_sequence("@a"), Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn duplicates_arent_forks() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("feeds.cbor");
+
+        let mut log = MemLog::new();
+        let a1 = append_msg(&mut log, "@a", 1, "a1");
+        let copy = append_msg(&mut log, "@a", 1, "a1");
+        append_msg(&mut log, "@a", u64::MAX, "last");
+        append_msg(&mut log, "@a", u64::MAX, "last");
+        let mut view = SsbFeedView::open(&path)?;
+        view.catch_up(&log);
+        assert!(view.forks().is_empty());
+        assert_eq!(view.get("@a", 1), Some(a1));
+        view.save()?;
+
+        let view = SsbFeedView::open(&path)?;
+        assert!(view.forks().is_empty());
+        assert_eq!(view.latest_sequence("@a"), Some(u64::MAX));
+
+        // Copies of a forked message are listed with the fork.
+        let fork = append_msg(&mut log, "@a", 1, "a1-fork");
+        let mut view = SsbFeedView::open(&path)?;
+        view.catch_up(&log);
+        let expected = vec![Fork {
+            author: "@a".to_string(),
+            sequence: 1,
+            entries: vec![a1, copy, fork],
+        }];
+        assert_eq!(view.forks(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn go_offset_log() {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("test_vecs/four_ssb_messages");
+        let log = GoOffsetLog::open_read_only(d).unwrap();
+
+        let mut view = SsbFeedView::new();
+        view.catch_up(&log);
+        assert_eq!(view.latest_sequences().len(), 1);
+        let (author, latest) = view.latest_sequences().iter().next().unwrap();
+        assert_eq!(*latest, 2);
+        assert_eq!(view.get(author, 1), Some(0));
+        assert_eq!(view.get(author, 2), Some(447));
+    }
+}
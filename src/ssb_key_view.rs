@@ -0,0 +1,190 @@
+use crate::flume_log::*;
+use crate::flume_view::*;
+use crate::index_view::IndexView;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use serde_json::Value;
+use std::path::Path;
+
+// Bump this whenever `message_key` changes.
+const VERSION: u32 = 1;
+
+/// An index from SSB message keys (`%<hash>.sha256`) to the sequences of the
+/// messages in the log.
+///
+/// It works with any log whose entries are SSB messages in the usual
+/// `{"key": ..., "value": ..., "timestamp": ...}` form, which includes
+/// `OffsetLog`s written by the JS `ssb-db` and `GoOffsetLog`s.
+///
+/// ```
+/// use flumedb::{FlumeLog, MemLog, SsbKeyView};
+///
+/// let mut log = MemLog::new();
+/// let msg = br#"{"key": "%AAA=.sha256", "value": {}, "timestamp": 0}"#;
+/// log.append(msg).unwrap();
+///
+/// let mut keys = SsbKeyView::new();
+/// keys.catch_up(&log);
+/// assert_eq!(keys.get("%AAA=.sha256"), Some(0));
+/// assert_eq!(keys.get_by_key(&log, "%AAA=.sha256").unwrap(), &msg[..]);
+/// ```
+pub struct SsbKeyView {
+    index: IndexView<String>,
+}
+
+impl SsbKeyView {
+    /// Creates a view that's kept in memory only.
+    pub fn new() -> SsbKeyView {
+        SsbKeyView {
+            index: IndexView::new(VERSION, message_key),
+        }
+    }
+
+    /// Creates a view that's saved to the file at `path`, starting from the
+    /// state saved there if there is one.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SsbKeyView, Error> {
+        Ok(SsbKeyView {
+            index: IndexView::open(path, VERSION, message_key)?,
+        })
+    }
+
+    /// Writes the view to its file. Does nothing for in-memory views.
+    pub fn save(&self) -> Result<(), Error> {
+        self.index.save()
+    }
+
+    /// Indexes the messages in `log` that haven't been indexed yet.
+    pub fn catch_up<L, I>(&mut self, log: &L)
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        self.index.catch_up(log)
+    }
+
+    /// The sequence of the message with the key `key`, if it's been indexed.
+    ///
+    /// If the log holds the same message more than once, this is the first copy.
+    pub fn get(&self, key: &str) -> Option<Sequence> {
+        self.index.get(key).first().cloned()
+    }
+
+    /// Reads the message with the key `key` from `log`, the log this view
+    /// indexes. Returns `None` if the message hasn't been indexed, or can't
+    /// be read from the log.
+    pub fn get_by_key<L, I>(&self, log: &L, key: &str) -> Option<Vec<u8>>
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        let seq = self.get(key)?;
+        log.iter_at_offset(seq)
+            .next()
+            .filter(|entry| entry.offset == seq)
+            .map(|entry| entry.data)
+    }
+
+    /// The number of messages indexed.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// The sequence of the last entry the view has seen, if any.
+    pub fn since(&self) -> Option<Sequence> {
+        self.index.since()
+    }
+}
+
+impl Default for SsbKeyView {
+    fn default() -> SsbKeyView {
+        SsbKeyView::new()
+    }
+}
+
+impl FlumeView for SsbKeyView {
+    fn append(&mut self, seq: Sequence, item: &[u8]) {
+        self.index.append(seq, item)
+    }
+
+    fn latest(&self) -> Sequence {
+        self.index.latest()
+    }
+}
+
+/// The key of the SSB message `data`. Entries that aren't SSB messages
+/// have no key.
+fn message_key(_seq: Sequence, data: &[u8]) -> Vec<String> {
+    serde_json::from_slice::<Value>(data)
+        .ok()
+        .and_then(|v| v["key"].as_str().map(|key| key.to_string()))
+        .filter(|key| key.starts_with('%'))
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::*;
+    use crate::go_offset_log::GoOffsetLog;
+    use crate::offset_log::OffsetLog;
+    use crate::ssb_key_view::*;
+    use serde_json::Value;
+    use std::path::PathBuf;
+    use tempfile::{tempdir, tempfile};
+
+    fn go_log() -> GoOffsetLog {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("test_vecs/four_ssb_messages");
+        GoOffsetLog::open_read_only(d).unwrap()
+    }
+
+    fn key_of(data: &[u8]) -> String {
+        let v: Value = serde_json::from_slice(data).unwrap();
+        v["key"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn go_offset_log() {
+        let log = go_log();
+        let mut view = SsbKeyView::new();
+        view.catch_up(&log);
+
+        assert_eq!(view.len(), 2);
+        for entry in log.iter() {
+            let key = key_of(&entry.data);
+            assert!(key.ends_with(".sha256"));
+            assert_eq!(view.get(&key), Some(entry.offset));
+            assert_eq!(view.get_by_key(&log, &key), Some(entry.data));
+        }
+        assert_eq!(view.get("%nope.sha256"), None);
+        assert_eq!(view.get_by_key(&log, "%nope.sha256"), None);
+    }
+
+    #[test]
+    fn offset_log() -> Result<(), Error> {
+        let go = go_log();
+        let mut log = OffsetLog::<u32>::from_file(tempfile()?)?;
+        log.append(br#"{"value": "not an ssb message"}"#)?;
+        let msgs: Vec<Vec<u8>> = go.iter().map(|e| e.data).collect();
+        let seqs = log.append_batch(&msgs)?;
+
+        let dir = tempdir()?;
+        let path = dir.path().join("keys.cbor");
+        let mut view = SsbKeyView::open(&path)?;
+        view.catch_up(&log);
+        view.save()?;
+
+        let view = SsbKeyView::open(&path)?;
+        assert_eq!(view.len(), 2);
+        assert_eq!(view.since(), log.latest());
+        for (msg, seq) in msgs.iter().zip(seqs) {
+            assert_eq!(view.get(&key_of(msg)), Some(seq));
+            assert_eq!(view.get_by_key(&log, &key_of(msg)).as_ref(), Some(msg));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,386 @@
+use crate::flume_log::*;
+use crate::flume_view::*;
+use crate::index_view::IndexView;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use serde_json::Value;
+use ssb_multiformats::multifeed::Multifeed;
+use ssb_multiformats::multihash::Multihash;
+use std::ops::Bound;
+use std::path::Path;
+
+// Bump this whenever `link_keys` changes.
+const VERSION: u32 = 1;
+
+/// A reference from one SSB message to a feed, message or blob.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Link {
+    /// The key of the message the link is in.
+    pub source: String,
+    /// The `@feed`, `%message` or `&blob` id the link points to.
+    pub target: String,
+    /// Where the link is in the message's content: the object keys leading
+    /// to it, joined with `.`. Array indexes are left out, as is a final
+    /// `link` key, so both `{"mentions": [{"link": "@..."}]}` and
+    /// `{"mentions": ["@..."]}` link with rel `mentions`.
+    pub rel: String,
+    /// The log sequence of the message the link is in.
+    pub seq: Sequence,
+}
+
+// Each link is indexed once in each direction.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+enum LinkKey {
+    // (source, rel, target)
+    From(String, String, String),
+    // (target, rel, source)
+    To(String, String, String),
+}
+
+/// An index of the links between SSB messages, feeds and blobs, like the JS
+/// `ssb-links` and `ssb-backlinks` plugins.
+///
+/// Links are found by parsing every string in a message's content as an
+/// SSB id, so messages that point at a thread root, vote on a message or
+/// mention a feed can all be found from the message, feed or blob they
+/// point at.
+///
+/// ```
+/// use flumedb::{FlumeLog, MemLog, SsbLinksView};
+///
+/// let root = "%AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=.sha256";
+/// let mut log = MemLog::new();
+/// let reply = format!(
+///     r#"{{"key": "%reply", "value": {{"content": {{"type": "post", "root": "{}"}}}}}}"#,
+///     root
+/// );
+/// log.append(reply.as_bytes()).unwrap();
+///
+/// let mut links = SsbLinksView::new();
+/// links.catch_up(&log);
+/// let replies: Vec<String> = links.links_to(root, Some("root")).map(|l| l.source).collect();
+/// assert_eq!(replies, &["%reply"]);
+/// ```
+pub struct SsbLinksView {
+    index: IndexView<LinkKey>,
+}
+
+impl SsbLinksView {
+    /// Creates a view that's kept in memory only.
+    pub fn new() -> SsbLinksView {
+        SsbLinksView {
+            index: IndexView::new(VERSION, link_keys),
+        }
+    }
+
+    /// Creates a view that's saved to the file at `path`, starting from the
+    /// state saved there if there is one.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SsbLinksView, Error> {
+        Ok(SsbLinksView {
+            index: IndexView::open(path, VERSION, link_keys)?,
+        })
+    }
+
+    /// Writes the view to its file. Does nothing for in-memory views.
+    pub fn save(&self) -> Result<(), Error> {
+        self.index.save()
+    }
+
+    /// Indexes the messages in `log` that haven't been indexed yet.
+    pub fn catch_up<L, I>(&mut self, log: &L)
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        self.index.catch_up(log)
+    }
+
+    /// The links in the message with the key `source`, optionally only those
+    /// with the rel `rel`, ordered by rel and then target. Call `rev()` on
+    /// the result for the reverse order.
+    pub fn links_from<'a>(
+        &'a self,
+        source: &str,
+        rel: Option<&str>,
+    ) -> impl DoubleEndedIterator<Item = Link> + 'a {
+        let (lower, upper) = key_range(LinkKey::From, source, rel);
+        self.index
+            .range((lower, upper))
+            .map(|(key, seq)| match key {
+                LinkKey::From(source, rel, target) => Link {
+                    source: source.clone(),
+                    target: target.clone(),
+                    rel: rel.clone(),
+                    seq,
+                },
+                LinkKey::To(..) => unreachable!(),
+            })
+    }
+
+    /// The links to the feed, message or blob `target`, optionally only
+    /// those with the rel `rel`, ordered by rel and then source. Call
+    /// `rev()` on the result for the reverse order.
+    pub fn links_to<'a>(
+        &'a self,
+        target: &str,
+        rel: Option<&str>,
+    ) -> impl DoubleEndedIterator<Item = Link> + 'a {
+        let (lower, upper) = key_range(LinkKey::To, target, rel);
+        self.index
+            .range((lower, upper))
+            .map(|(key, seq)| match key {
+                LinkKey::To(target, rel, source) => Link {
+                    source: source.clone(),
+                    target: target.clone(),
+                    rel: rel.clone(),
+                    seq,
+                },
+                LinkKey::From(..) => unreachable!(),
+            })
+    }
+
+    /// The sequence of the last entry the view has seen, if any.
+    pub fn since(&self) -> Option<Sequence> {
+        self.index.since()
+    }
+}
+
+impl Default for SsbLinksView {
+    fn default() -> SsbLinksView {
+        SsbLinksView::new()
+    }
+}
+
+impl FlumeView for SsbLinksView {
+    fn append(&mut self, seq: Sequence, item: &[u8]) {
+        self.index.append(seq, item)
+    }
+
+    fn latest(&self) -> Sequence {
+        self.index.latest()
+    }
+}
+
+/// The bounds of the keys made by `key` that start with `id`, and `rel` if
+/// given. No string sorts between `s` and `s + "\0"`, so appending a nul
+/// gives an upper bound just past every key with the prefix.
+fn key_range<F>(key: F, id: &str, rel: Option<&str>) -> (Bound<LinkKey>, Bound<LinkKey>)
+where
+    F: Fn(String, String, String) -> LinkKey,
+{
+    match rel {
+        Some(rel) => (
+            Bound::Included(key(id.to_string(), rel.to_string(), String::new())),
+            Bound::Excluded(key(id.to_string(), format!("{}\0", rel), String::new())),
+        ),
+        None => (
+            Bound::Included(key(id.to_string(), String::new(), String::new())),
+            Bound::Excluded(key(format!("{}\0", id), String::new(), String::new())),
+        ),
+    }
+}
+
+/// Both index keys of every link in the SSB message `data`.
+fn link_keys(_seq: Sequence, data: &[u8]) -> Vec<LinkKey> {
+    let msg: Value = match serde_json::from_slice(data) {
+        Ok(msg) => msg,
+        Err(_) => return Vec::new(),
+    };
+    let source = match msg["key"].as_str() {
+        Some(key) => key,
+        None => return Vec::new(),
+    };
+
+    let mut links = Vec::new();
+    find_links(&msg["value"]["content"], &mut Vec::new(), &mut links);
+    links
+        .into_iter()
+        .flat_map(|(rel, target)| {
+            vec![
+                LinkKey::From(source.to_string(), rel.clone(), target.clone()),
+                LinkKey::To(target, rel, source.to_string()),
+            ]
+        })
+        .collect()
+}
+
+/// Collects the `(rel, target)` of every link in `value`, which is at `path`
+/// in the message's content.
+fn find_links(value: &Value, path: &mut Vec<String>, links: &mut Vec<(String, String)>) {
+    match value {
+        Value::String(s) if is_ssb_id(s) => links.push((path.join("."), s.clone())),
+        Value::Array(items) => {
+            for item in items {
+                find_links(item, path, links);
+            }
+        }
+        Value::Object(map) => {
+            for (key, value) in map {
+                if key == "link" {
+                    find_links(value, path, links);
+                } else {
+                    path.push(key.clone());
+                    find_links(value, path, links);
+                    path.pop();
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether `s` is a feed, message or blob id in its legacy encoding.
+fn is_ssb_id(s: &str) -> bool {
+    match s.as_bytes().first() {
+        Some(b'@') => {
+            matches!(Multifeed::from_legacy(s.as_bytes()), Ok((_, rest)) if rest.is_empty())
+        }
+        Some(b'%') | Some(b'&') => {
+            matches!(Multihash::from_legacy(s.as_bytes()), Ok((_, rest)) if rest.is_empty())
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::*;
+    use crate::mem_log::MemLog;
+    use crate::ssb_links_view::*;
+    use serde_json::json;
+    use ssb_multiformats::multikey::Multikey;
+    use tempfile::tempdir;
+
+    fn msg_id(n: u8) -> String {
+        Multihash::Message([n; 32]).to_legacy_string()
+    }
+
+    fn feed_id(n: u8) -> String {
+        Multikey::from_ed25519(&[n; 32]).to_legacy_string()
+    }
+
+    fn blob_id(n: u8) -> String {
+        Multihash::Blob([n; 32]).to_legacy_string()
+    }
+
+    fn append_msg(log: &mut MemLog, key: &str, content: Value) -> Sequence {
+        let msg = json!({"key": key, "value": {"author": feed_id(0), "content": content}});
+        log.append(&serde_json::to_vec(&msg).unwrap()).unwrap()
+    }
+
+    fn pairs<I: Iterator<Item = Link>>(links: I) -> Vec<(String, String)> {
+        links.map(|l| (l.rel, l.target)).collect()
+    }
+
+    #[test]
+    fn finds_links() {
+        let mut content = json!({
+            "type": "post",
+            "text": "not a link: @nope",
+            "root": msg_id(1),
+            "branch": [msg_id(2), msg_id(3)],
+            "mentions": [
+                {"link": feed_id(4), "name": "four"},
+                {"link": blob_id(5), "type": "image/png"},
+                "#channel",
+            ],
+            "vote": {"link": msg_id(1), "value": 1},
+        });
+        let mut links = Vec::new();
+        find_links(&content, &mut Vec::new(), &mut links);
+        links.sort();
+        let mut expected = vec![
+            ("root".to_string(), msg_id(1)),
+            ("branch".to_string(), msg_id(2)),
+            ("branch".to_string(), msg_id(3)),
+            ("mentions".to_string(), feed_id(4)),
+            ("mentions".to_string(), blob_id(5)),
+            ("vote".to_string(), msg_id(1)),
+        ];
+        expected.sort();
+        assert_eq!(links, expected);
+
+        content["root"] = json!("%tooshort.sha256");
+        let mut links = Vec::new();
+        find_links(&content, &mut Vec::new(), &mut links);
+        assert!(!links.iter().any(|(rel, _)| rel == "root"));
+    }
+
+    #[test]
+    fn both_directions() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("links.cbor");
+        let mut log = MemLog::new();
+        let root = msg_id(1);
+
+        let post = append_msg(
+            &mut log,
+            &root,
+            json!({"type": "post", "mentions": [feed_id(9)]}),
+        );
+        let reply = append_msg(
+            &mut log,
+            &msg_id(2),
+            json!({"type": "post", "root": root, "branch": root}),
+        );
+        log.append(b"not a message")?;
+        let mut view = SsbLinksView::open(&path)?;
+        view.catch_up(&log);
+        view.save()?;
+
+        let vote = append_msg(
+            &mut log,
+            &msg_id(3),
+            json!({"type": "vote", "vote": {"link": root, "value": 1}}),
+        );
+        let mut view = SsbLinksView::open(&path)?;
+        view.catch_up(&log);
+
+        let backlinks: Vec<Link> = view.links_to(&root, None).collect();
+        assert_eq!(
+            backlinks,
+            &[
+                Link {
+                    source: msg_id(2),
+                    target: root.clone(),
+                    rel: "branch".to_string(),
+                    seq: reply
+                },
+                Link {
+                    source: msg_id(2),
+                    target: root.clone(),
+                    rel: "root".to_string(),
+                    seq: reply
+                },
+                Link {
+                    source: msg_id(3),
+                    target: root.clone(),
+                    rel: "vote".to_string(),
+                    seq: vote
+                },
+            ]
+        );
+        let votes: Vec<Sequence> = view.links_to(&root, Some("vote")).map(|l| l.seq).collect();
+        assert_eq!(votes, &[vote]);
+        let rels: Vec<String> = view.links_to(&root, None).rev().map(|l| l.rel).collect();
+        assert_eq!(rels, &["vote", "root", "branch"]);
+
+        assert_eq!(
+            pairs(view.links_from(&msg_id(2), None)),
+            &[
+                ("branch".to_string(), root.clone()),
+                ("root".to_string(), root.clone())
+            ]
+        );
+        assert_eq!(
+            pairs(view.links_from(&msg_id(2), Some("root"))),
+            &[("root".to_string(), root.clone())]
+        );
+        assert_eq!(view.links_from(&msg_id(2), Some("roo")).count(), 0);
+
+        let mentions: Vec<Sequence> = view.links_to(&feed_id(9), None).map(|l| l.seq).collect();
+        assert_eq!(mentions, &[post]);
+        assert_eq!(view.links_to(&msg_id(4), None).count(), 0);
+        Ok(())
+    }
+}
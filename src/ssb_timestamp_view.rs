@@ -0,0 +1,218 @@
+use crate::flume_log::*;
+use crate::flume_view::*;
+use crate::index_view::{IndexView, KeyExtractor};
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use serde_json::Value;
+use std::ops::RangeBounds;
+use std::path::Path;
+
+/// Which of an SSB message's timestamps a [`SsbTimestampView`] indexes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampKind {
+    /// The top-level `timestamp`: when this database received the message.
+    Received,
+    /// `value.timestamp`: when the author says they wrote the message.
+    Claimed,
+}
+
+impl TimestampKind {
+    // The two kinds get different versions, so that opening a saved view
+    // as the wrong kind rebuilds it.
+    fn version(self) -> u32 {
+        match self {
+            TimestampKind::Received => 1,
+            TimestampKind::Claimed => 101,
+        }
+    }
+
+    fn extractor(self) -> KeyExtractor<u64> {
+        match self {
+            TimestampKind::Received => |_, data| timestamp_at(data, &["timestamp"]),
+            TimestampKind::Claimed => |_, data| timestamp_at(data, &["value", "timestamp"]),
+        }
+    }
+}
+
+/// An index of SSB messages by received or claimed timestamp, in
+/// milliseconds since the Unix epoch. Fractions of a millisecond are
+/// dropped; messages with the same timestamp are in log order.
+///
+/// ```
+/// use flumedb::{FlumeLog, MemLog, SsbTimestampView, TimestampKind};
+///
+/// let mut log = MemLog::new();
+/// for ts in &[300, 100, 200] {
+///     let msg = format!(r#"{{"key": "%{}", "value": {{}}, "timestamp": {}}}"#, ts, ts);
+///     log.append(msg.as_bytes()).unwrap();
+/// }
+///
+/// let mut by_received = SsbTimestampView::new(TimestampKind::Received);
+/// by_received.catch_up(&log);
+/// let newest_first = by_received.page(.., None, true, 2);
+/// assert_eq!(newest_first, &[(300, 0), (200, 2)]);
+/// let next = by_received.page(.., newest_first.last(), true, 2);
+/// assert_eq!(next, &[(100, 1)]);
+/// ```
+pub struct SsbTimestampView {
+    kind: TimestampKind,
+    index: IndexView<u64>,
+}
+
+impl SsbTimestampView {
+    /// Creates a view that's kept in memory only.
+    pub fn new(kind: TimestampKind) -> SsbTimestampView {
+        SsbTimestampView {
+            kind,
+            index: IndexView::new(kind.version(), kind.extractor()),
+        }
+    }
+
+    /// Creates a view that's saved to the file at `path`, starting from the
+    /// state saved there if there is one.
+    pub fn open<P: AsRef<Path>>(path: P, kind: TimestampKind) -> Result<SsbTimestampView, Error> {
+        Ok(SsbTimestampView {
+            kind,
+            index: IndexView::open(path, kind.version(), kind.extractor())?,
+        })
+    }
+
+    /// Writes the view to its file. Does nothing for in-memory views.
+    pub fn save(&self) -> Result<(), Error> {
+        self.index.save()
+    }
+
+    /// Indexes the messages in `log` that haven't been indexed yet.
+    pub fn catch_up<L, I>(&mut self, log: &L)
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        self.index.catch_up(log)
+    }
+
+    pub fn kind(&self) -> TimestampKind {
+        self.kind
+    }
+
+    /// Up to `limit` messages with timestamps in `range`, as pairs of
+    /// timestamp and log sequence, oldest first or, if `reverse` is set,
+    /// newest first. Pass the last pair of one page as `after` to get the
+    /// next.
+    pub fn page<R: RangeBounds<u64>>(
+        &self,
+        range: R,
+        after: Option<&(u64, Sequence)>,
+        reverse: bool,
+        limit: usize,
+    ) -> Vec<(u64, Sequence)> {
+        self.index.page(range, after, reverse, limit)
+    }
+
+    /// The sequence of the last entry the view has seen, if any.
+    pub fn since(&self) -> Option<Sequence> {
+        self.index.since()
+    }
+}
+
+impl FlumeView for SsbTimestampView {
+    fn append(&mut self, seq: Sequence, item: &[u8]) {
+        self.index.append(seq, item)
+    }
+
+    fn latest(&self) -> Sequence {
+        self.index.latest()
+    }
+}
+
+/// The timestamp at `path` in the SSB message `data`, in whole milliseconds.
+pub(crate) fn timestamp_at(data: &[u8], path: &[&str]) -> Vec<u64> {
+    let msg: Value = match serde_json::from_slice(data) {
+        Ok(msg) => msg,
+        Err(_) => return Vec::new(),
+    };
+    path.iter()
+        .fold(&msg, |v, key| &v[*key])
+        .as_f64()
+        .filter(|ts| *ts >= 0.0)
+        .map(|ts| ts as u64)
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::*;
+    use crate::go_offset_log::GoOffsetLog;
+    use crate::mem_log::MemLog;
+    use crate::ssb_timestamp_view::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+    use tempfile::tempdir;
+
+    fn append_msg(log: &mut MemLog, received: f64, claimed: u64) -> Sequence {
+        let msg = json!({
+            "key": format!("%{}", claimed),
+            "value": {"timestamp": claimed},
+            "timestamp": received,
+        });
+        log.append(&serde_json::to_vec(&msg).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn received_and_claimed() -> Result<(), Error> {
+        let mut log = MemLog::new();
+        let a = append_msg(&mut log, 1000.5, 30);
+        let b = append_msg(&mut log, 1001.0, 10);
+        log.append(b"not a message")?;
+        let c = append_msg(&mut log, 1000.9, 20);
+
+        let mut received = SsbTimestampView::new(TimestampKind::Received);
+        let mut claimed = SsbTimestampView::new(TimestampKind::Claimed);
+        received.catch_up(&log);
+        claimed.catch_up(&log);
+
+        assert_eq!(
+            received.page(.., None, false, 10),
+            &[(1000, a), (1000, c), (1001, b)]
+        );
+        assert_eq!(received.page(1001.., None, false, 10), &[(1001, b)]);
+        assert_eq!(claimed.page(..=20, None, true, 10), &[(20, c), (10, b)]);
+        assert_eq!(claimed.page(.., Some(&(20, c)), false, 10), &[(30, a)]);
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_kind_is_rebuilt() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("timestamps.cbor");
+        let mut log = MemLog::new();
+        append_msg(&mut log, 5.0, 50);
+
+        let mut view = SsbTimestampView::open(&path, TimestampKind::Received)?;
+        view.catch_up(&log);
+        view.save()?;
+        assert_eq!(
+            SsbTimestampView::open(&path, TimestampKind::Received)?.since(),
+            Some(0)
+        );
+
+        let mut view = SsbTimestampView::open(&path, TimestampKind::Claimed)?;
+        assert_eq!(view.since(), None);
+        view.catch_up(&log);
+        assert_eq!(view.page(.., None, false, 10), &[(50, 0)]);
+        Ok(())
+    }
+
+    #[test]
+    fn go_offset_log() {
+        let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("test_vecs/four_ssb_messages");
+        let log = GoOffsetLog::open_read_only(d).unwrap();
+
+        let mut view = SsbTimestampView::new(TimestampKind::Claimed);
+        view.catch_up(&log);
+        let seqs: Vec<Sequence> = view.page(.., None, false, 10).iter().map(|e| e.1).collect();
+        assert_eq!(seqs, &[0, 447]);
+    }
+}
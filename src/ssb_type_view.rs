@@ -0,0 +1,192 @@
+use crate::flume_log::*;
+use crate::flume_view::*;
+use crate::index_view::IndexView;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use crate::ssb_timestamp_view::timestamp_at;
+use serde_json::Value;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+
+// Bump this whenever `type_key` changes.
+const VERSION: u32 = 1;
+
+/// An index of SSB messages by `value.content.type` and then received
+/// timestamp, like the JS `ssb-query`'s `type` index. Messages whose
+/// content isn't an object with a string `type` (private messages, for
+/// instance) aren't indexed.
+///
+/// ```
+/// use flumedb::{FlumeLog, MemLog, SsbTypeView};
+///
+/// let mut log = MemLog::new();
+/// for (ts, ty) in &[(1, "post"), (2, "about"), (3, "post")] {
+///     let msg = format!(
+///         r#"{{"key": "%{}", "value": {{"content": {{"type": "{}"}}}}, "timestamp": {}}}"#,
+///         ts, ty, ts
+///     );
+///     log.append(msg.as_bytes()).unwrap();
+/// }
+///
+/// let mut by_type = SsbTypeView::new();
+/// by_type.catch_up(&log);
+/// assert_eq!(by_type.page("post", .., None, true, 10), &[(3, 2), (1, 0)]);
+/// assert_eq!(by_type.count("about"), 1);
+/// ```
+pub struct SsbTypeView {
+    index: IndexView<(String, u64)>,
+}
+
+impl SsbTypeView {
+    /// Creates a view that's kept in memory only.
+    pub fn new() -> SsbTypeView {
+        SsbTypeView {
+            index: IndexView::new(VERSION, type_key),
+        }
+    }
+
+    /// Creates a view that's saved to the file at `path`, starting from the
+    /// state saved there if there is one.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SsbTypeView, Error> {
+        Ok(SsbTypeView {
+            index: IndexView::open(path, VERSION, type_key)?,
+        })
+    }
+
+    /// Writes the view to its file. Does nothing for in-memory views.
+    pub fn save(&self) -> Result<(), Error> {
+        self.index.save()
+    }
+
+    /// Indexes the messages in `log` that haven't been indexed yet.
+    pub fn catch_up<L, I>(&mut self, log: &L)
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        self.index.catch_up(log)
+    }
+
+    /// Up to `limit` messages of type `ty` received at times in `range`, as
+    /// pairs of received timestamp and log sequence, oldest first or, if
+    /// `reverse` is set, newest first. Pass the last pair of one page as
+    /// `after` to get the next.
+    pub fn page<R: RangeBounds<u64>>(
+        &self,
+        ty: &str,
+        range: R,
+        after: Option<&(u64, Sequence)>,
+        reverse: bool,
+        limit: usize,
+    ) -> Vec<(u64, Sequence)> {
+        let key = |ts| (ty.to_string(), ts);
+        let lower = match range.start_bound() {
+            Bound::Included(ts) => Bound::Included(key(*ts)),
+            Bound::Excluded(ts) => Bound::Excluded(key(*ts)),
+            Bound::Unbounded => Bound::Included(key(0)),
+        };
+        let upper = match range.end_bound() {
+            Bound::Included(ts) => Bound::Included(key(*ts)),
+            Bound::Excluded(ts) => Bound::Excluded(key(*ts)),
+            Bound::Unbounded => Bound::Included(key(u64::MAX)),
+        };
+        let after = after.map(|(ts, seq)| (key(*ts), *seq));
+
+        self.index
+            .page((lower, upper), after.as_ref(), reverse, limit)
+            .into_iter()
+            .map(|((_, ts), seq)| (ts, seq))
+            .collect()
+    }
+
+    /// The number of messages of type `ty`.
+    pub fn count(&self, ty: &str) -> usize {
+        let all = (ty.to_string(), 0)..=(ty.to_string(), u64::MAX);
+        self.index.range(all).count()
+    }
+
+    /// The sequence of the last entry the view has seen, if any.
+    pub fn since(&self) -> Option<Sequence> {
+        self.index.since()
+    }
+}
+
+impl Default for SsbTypeView {
+    fn default() -> SsbTypeView {
+        SsbTypeView::new()
+    }
+}
+
+impl FlumeView for SsbTypeView {
+    fn append(&mut self, seq: Sequence, item: &[u8]) {
+        self.index.append(seq, item)
+    }
+
+    fn latest(&self) -> Sequence {
+        self.index.latest()
+    }
+}
+
+/// The content type and received timestamp of the SSB message `data`.
+fn type_key(_seq: Sequence, data: &[u8]) -> Vec<(String, u64)> {
+    let ty = match serde_json::from_slice::<Value>(data) {
+        Ok(msg) => match msg["value"]["content"]["type"].as_str() {
+            Some(ty) => ty.to_string(),
+            None => return Vec::new(),
+        },
+        Err(_) => return Vec::new(),
+    };
+    timestamp_at(data, &["timestamp"])
+        .into_iter()
+        .map(|ts| (ty.clone(), ts))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::*;
+    use crate::mem_log::MemLog;
+    use crate::ssb_type_view::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    fn append_msg(log: &mut MemLog, received: u64, content: Value) -> Sequence {
+        let msg = json!({"key": "%k", "value": {"content": content}, "timestamp": received});
+        log.append(&serde_json::to_vec(&msg).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn pages_by_type() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("types.cbor");
+        let mut log = MemLog::new();
+        let p1 = append_msg(&mut log, 10, json!({"type": "post"}));
+        let a1 = append_msg(&mut log, 20, json!({"type": "about"}));
+        append_msg(&mut log, 30, json!("boxed.box"));
+        let p2 = append_msg(&mut log, 25, json!({"type": "post"}));
+        let pa = append_msg(&mut log, 40, json!({"type": "postal"}));
+
+        let mut view = SsbTypeView::open(&path)?;
+        view.catch_up(&log);
+        view.save()?;
+        let p3 = append_msg(&mut log, 50, json!({"type": "post"}));
+        let mut view = SsbTypeView::open(&path)?;
+        view.catch_up(&log);
+
+        assert_eq!(view.count("post"), 3);
+        assert_eq!(view.count("postal"), 1);
+        assert_eq!(view.count("vote"), 0);
+        assert_eq!(view.page("about", .., None, false, 10), &[(20, a1)]);
+        assert_eq!(view.page("postal", .., None, true, 10), &[(40, pa)]);
+
+        let newest = view.page("post", .., None, true, 2);
+        assert_eq!(newest, &[(50, p3), (25, p2)]);
+        assert_eq!(view.page("post", .., newest.last(), true, 2), &[(10, p1)]);
+        assert_eq!(view.page("post", 20..30, None, false, 10), &[(25, p2)]);
+        assert_eq!(
+            view.page("post", ..=25, Some(&(10, p1)), false, 10),
+            &[(25, p2)]
+        );
+        Ok(())
+    }
+}
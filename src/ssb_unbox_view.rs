@@ -0,0 +1,300 @@
+use crate::flume_view::*;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+use serde_json::Value;
+use ssb_crypto::ephemeral::{derive_shared_secret, sk_to_curve, EphPublicKey, EphSecretKey};
+use ssb_crypto::secretbox::{Hmac, Key, Nonce};
+use ssb_crypto::SecretKey;
+use std::collections::BTreeMap;
+
+// The most recipients a private-box message can have, and so the most
+// header slots that are worth trying.
+const MAX_RECIPIENTS: usize = 7;
+
+// Each header slot is a secret box holding the number of recipients and
+// the key to the body.
+const HEADER_SIZE: usize = Hmac::SIZE + 1 + Key::SIZE;
+
+/// A view that decrypts the private SSB messages in a log that can be read
+/// with the local keys, like the JS `ssb-db`'s unboxer.
+///
+/// A private message has a `value.content` that's a `private-box`
+/// ciphertext (`<base64>.box`) instead of an object. For the ones this view
+/// can open, it keeps a copy of the log entry whose `value.content` is the
+/// decrypted content and whose `value.private` is `true`. The copies are
+/// only kept in memory, so that decrypted messages never reach the disk.
+///
+/// Other views can index the decrypted messages by catching up on
+/// [`unboxed`](SsbUnboxView::unboxed) instead of the log:
+///
+/// ```
+/// use flumedb::{FlumeLog, MemLog, SsbTypeView, SsbUnboxView};
+///
+/// let mut log = MemLog::new();
+/// let msg = br#"{"key": "%A", "value": {"content": {"type": "post"}}, "timestamp": 1}"#;
+/// log.append(msg).unwrap();
+///
+/// let keys = ssb_crypto::Keypair::from_seed(&[1; 32]).unwrap();
+/// let mut unbox = SsbUnboxView::new(&[keys.secret]);
+/// unbox.catch_up(&log);
+///
+/// let mut by_type = SsbTypeView::new();
+/// by_type.catch_up(&unbox.unboxed(&log));
+/// assert_eq!(by_type.count("post"), 1);
+/// ```
+pub struct SsbUnboxView {
+    keys: Vec<EphSecretKey>,
+    unboxed: BTreeMap<Sequence, Vec<u8>>,
+    since: Option<Sequence>,
+}
+
+impl SsbUnboxView {
+    /// Creates a view that decrypts messages sent to any of the ed25519
+    /// secret keys `keys`.
+    pub fn new(keys: &[SecretKey]) -> SsbUnboxView {
+        SsbUnboxView {
+            keys: keys.iter().filter_map(sk_to_curve).collect(),
+            unboxed: BTreeMap::new(),
+            since: None,
+        }
+    }
+
+    /// Decrypts the messages in `log` that haven't been seen yet.
+    pub fn catch_up<L, I>(&mut self, log: &L)
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        let since = self.since;
+        catch_up(self, log, since);
+    }
+
+    /// The decrypted copy of the entry at `seq`, if it's a private message
+    /// this view could open.
+    pub fn get(&self, seq: Sequence) -> Option<&[u8]> {
+        self.unboxed.get(&seq).map(|data| data.as_slice())
+    }
+
+    /// The sequences of the private messages this view has opened.
+    pub fn sequences(&self) -> impl DoubleEndedIterator<Item = Sequence> + '_ {
+        self.unboxed.keys().cloned()
+    }
+
+    /// The number of private messages this view has opened.
+    pub fn len(&self) -> usize {
+        self.unboxed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.unboxed.is_empty()
+    }
+
+    /// The sequence of the last entry the view has seen, if any.
+    pub fn since(&self) -> Option<Sequence> {
+        self.since
+    }
+
+    /// `log`, with the private messages this view has opened replaced by
+    /// their decrypted copies. Entries after [`since`](SsbUnboxView::since)
+    /// aren't decrypted, so catch this view up first.
+    pub fn unboxed<'a, L>(&'a self, log: &'a L) -> UnboxedLog<'a, L> {
+        UnboxedLog { view: self, log }
+    }
+
+    /// The decrypted copy of the log entry `data`, if it's a private message
+    /// for one of the keys.
+    fn unbox_entry(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let mut entry: Value = serde_json::from_slice(data).ok()?;
+        let boxed = entry["value"]["content"].as_str()?;
+        let content = self.unbox(boxed)?;
+
+        let value = entry["value"].as_object_mut()?;
+        value.insert("content".to_string(), content);
+        value.insert("private".to_string(), Value::Bool(true));
+        serde_json::to_vec(&entry).ok()
+    }
+
+    /// Opens the `private-box` ciphertext `boxed`, if it was sent to one of
+    /// the keys.
+    fn unbox(&self, boxed: &str) -> Option<Value> {
+        let bytes = base64::decode(boxed.strip_suffix(".box")?).ok()?;
+        if bytes.len() < Nonce::SIZE + EphPublicKey::SIZE + HEADER_SIZE + Hmac::SIZE {
+            return None;
+        }
+        let (nonce, rest) = bytes.split_at(Nonce::SIZE);
+        let (ephemeral, rest) = rest.split_at(EphPublicKey::SIZE);
+        let nonce = Nonce::from_slice(nonce)?;
+        let ephemeral = EphPublicKey::from_slice(ephemeral)?;
+
+        for key in &self.keys {
+            let header_key = Key(derive_shared_secret(key, &ephemeral)?.0);
+            for header in rest.chunks_exact(HEADER_SIZE).take(MAX_RECIPIENTS) {
+                let mut opened = [0; HEADER_SIZE - Hmac::SIZE];
+                if !header_key.open_attached_into(header, &nonce, &mut opened) {
+                    continue;
+                }
+                let body_key = Key::from_slice(&opened[1..])?;
+                let body = rest.get(opened[0] as usize * HEADER_SIZE..)?;
+                if body.len() < Hmac::SIZE {
+                    return None;
+                }
+                let mut content = vec![0; body.len() - Hmac::SIZE];
+                if !body_key.open_attached_into(body, &nonce, &mut content) {
+                    return None;
+                }
+                return serde_json::from_slice(&content).ok();
+            }
+        }
+        None
+    }
+}
+
+impl FlumeView for SsbUnboxView {
+    fn append(&mut self, seq: Sequence, item: &[u8]) {
+        if self.since.is_some_and(|since| seq <= since) {
+            return;
+        }
+        if let Some(unboxed) = self.unbox_entry(item) {
+            self.unboxed.insert(seq, unboxed);
+        }
+        self.since = Some(seq);
+    }
+
+    fn latest(&self) -> Sequence {
+        self.since.unwrap_or(0)
+    }
+}
+
+/// A log seen through an [`SsbUnboxView`]: the same entries, with the
+/// private messages the view has opened decrypted.
+pub struct UnboxedLog<'a, L> {
+    view: &'a SsbUnboxView,
+    log: &'a L,
+}
+
+impl<'a, L, I> IterAtOffset<UnboxedIter<'a, I>> for UnboxedLog<'a, L>
+where
+    L: IterAtOffset<I>,
+    I: Iterator<Item = LogEntry>,
+{
+    fn iter_at_offset(&self, offset: u64) -> UnboxedIter<'a, I> {
+        UnboxedIter {
+            view: self.view,
+            entries: self.log.iter_at_offset(offset),
+        }
+    }
+}
+
+pub struct UnboxedIter<'a, I> {
+    view: &'a SsbUnboxView,
+    entries: I,
+}
+
+impl<'a, I: Iterator<Item = LogEntry>> Iterator for UnboxedIter<'a, I> {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<LogEntry> {
+        let mut entry = self.entries.next()?;
+        if let Some(unboxed) = self.view.get(entry.offset) {
+            entry.data = unboxed.to_vec();
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::*;
+    use crate::mem_log::MemLog;
+    use crate::offset_log::OffsetLog;
+    use crate::ssb_type_view::SsbTypeView;
+    use crate::ssb_unbox_view::*;
+    use serde_json::json;
+    use ssb_crypto::ephemeral::{derive_shared_secret_pk, generate_ephemeral_keypair};
+    use ssb_crypto::Keypair;
+    use tempfile::tempfile;
+
+    /// Encrypts `content` for `recipients` like the JS `private-box` does.
+    fn private_box(content: &Value, recipients: &[&Keypair]) -> String {
+        let (ephemeral, ephemeral_secret) = generate_ephemeral_keypair();
+        let nonce = Nonce::generate();
+        let body_key = Key::generate();
+
+        let mut bytes = nonce.0.to_vec();
+        bytes.extend_from_slice(&ephemeral.0);
+        for recipient in recipients {
+            let shared = derive_shared_secret_pk(&ephemeral_secret, &recipient.public).unwrap();
+            let mut header = vec![recipients.len() as u8];
+            header.extend_from_slice(&body_key.0);
+            let mut sealed = [0; HEADER_SIZE];
+            Key(shared.0).seal_attached_into(&header, &nonce, &mut sealed);
+            bytes.extend_from_slice(&sealed);
+        }
+        let body = serde_json::to_vec(content).unwrap();
+        let mut sealed = vec![0; body.len() + Hmac::SIZE];
+        body_key.seal_attached_into(&body, &nonce, &mut sealed);
+        bytes.extend_from_slice(&sealed);
+
+        format!("{}.box", base64::encode(&bytes))
+    }
+
+    fn append_msg<L: FlumeLog>(log: &mut L, content: Value) -> Sequence {
+        let msg = json!({"key": "%k", "value": {"content": content}, "timestamp": 1});
+        log.append(&serde_json::to_vec(&msg).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn unboxes_messages_for_local_keys() -> Result<(), Error> {
+        let alice = Keypair::from_seed(&[1; 32]).unwrap();
+        let bob = Keypair::from_seed(&[2; 32]).unwrap();
+        let carol = Keypair::from_seed(&[3; 32]).unwrap();
+
+        let mut log = OffsetLog::<u32>::from_file(tempfile()?)?;
+        let public = append_msg(&mut log, json!({"type": "post"}));
+        let to_bob = append_msg(
+            &mut log,
+            json!(private_box(&json!({"type": "secret"}), &[&alice, &bob])),
+        );
+        let to_carol = append_msg(
+            &mut log,
+            json!(private_box(&json!({"type": "secret"}), &[&carol])),
+        );
+        append_msg(&mut log, json!("not even base64.box"));
+
+        let mut unbox = SsbUnboxView::new(&[bob.secret]);
+        unbox.catch_up(&log);
+        assert_eq!(unbox.since(), log.latest());
+        assert_eq!(unbox.sequences().collect::<Vec<_>>(), &[to_bob]);
+        assert!(unbox.get(public).is_none());
+        assert!(unbox.get(to_carol).is_none());
+
+        let opened: Value = serde_json::from_slice(unbox.get(to_bob).unwrap())?;
+        assert_eq!(opened["value"]["content"], json!({"type": "secret"}));
+        assert_eq!(opened["value"]["private"], true);
+        assert_eq!(opened["key"], "%k");
+
+        // Other views see the decrypted stream.
+        let mut by_type = SsbTypeView::new();
+        by_type.catch_up(&unbox.unboxed(&log));
+        assert_eq!(by_type.page("secret", .., None, false, 10), &[(1, to_bob)]);
+        assert_eq!(by_type.count("post"), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn catches_up_incrementally() {
+        let alice = Keypair::from_seed(&[1; 32]).unwrap();
+        let mut log = MemLog::new();
+        let mut unbox = SsbUnboxView::new(std::slice::from_ref(&alice.secret));
+
+        let first = append_msg(&mut log, json!(private_box(&json!({"n": 1}), &[&alice])));
+        unbox.catch_up(&log);
+        let second = append_msg(&mut log, json!(private_box(&json!({"n": 2}), &[&alice])));
+        unbox.catch_up(&log);
+        unbox.catch_up(&log);
+
+        assert_eq!(unbox.len(), 2);
+        assert_eq!(unbox.sequences().collect::<Vec<_>>(), &[first, second]);
+        assert!(SsbUnboxView::new(&[]).is_empty());
+    }
+}
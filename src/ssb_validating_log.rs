@@ -0,0 +1,492 @@
+use crate::flume_log::*;
+use crate::flume_view::*;
+use crate::iter_at_offset::IterAtOffset;
+use crate::json::{encode, write_json, write_str, Json};
+use crate::log_entry::LogEntry;
+use crate::ssb_feed_view::SsbFeedView;
+use ssb_multiformats::multihash::Multihash;
+use ssb_multiformats::multikey::Multikey;
+use thiserror::Error;
+
+/// The largest a message may be, in UTF-16 code units of its signed encoding.
+pub const MAX_MESSAGE_SIZE: usize = 8192;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum SsbValidationError {
+    #[error("Message is not a JSON object")]
+    NotAnObject {},
+    #[error("Message fields are missing, unexpected or out of order")]
+    NotCanonical {},
+    #[error("Message field `{field}` is invalid")]
+    InvalidField { field: &'static str },
+    #[error(
+        "Message is {size} bytes long, more than the limit of {} bytes",
+        MAX_MESSAGE_SIZE
+    )]
+    TooLarge { size: usize },
+    #[error("Message signature is not valid for its author")]
+    InvalidSignature {},
+    #[error("Message sequence is {found} but the feed expects {expected}")]
+    SequenceGap { expected: u64, found: u64 },
+    #[error("Message previous is {found:?} but the feed expects {expected:?}")]
+    PreviousMismatch {
+        expected: Option<String>,
+        found: Option<String>,
+    },
+}
+
+/// A log of SSB messages that only accepts valid messages that extend their
+/// author's feed, like the JS `ssb-validate`.
+///
+/// Each message (the `value` of a log entry) must have its fields in the
+/// legacy order, be signed by its author over its canonical encoding, and
+/// come straight after the latest message of its author's feed in the log:
+/// its `sequence` is one more, and its `previous` is that message's key.
+/// The first message of a feed has sequence 1 and a `null` previous.
+///
+/// Valid messages are stored as `{"key", "value", "timestamp"}` entries,
+/// with the key computed from the message, so that they can be read by
+/// the SSB views and by the JS `ssb-db`.
+pub struct SsbValidatingLog<L> {
+    log: L,
+    feeds: SsbFeedView,
+}
+
+impl<L: FlumeLog> SsbValidatingLog<L> {
+    /// Wraps `log`, indexing the feeds of the messages already in it.
+    pub fn new<I>(log: L) -> SsbValidatingLog<L>
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        SsbValidatingLog::with_feed_view(log, SsbFeedView::new())
+    }
+
+    /// Wraps `log`, using `feeds` (which may be behind the log) to check
+    /// the sequence and previous of new messages.
+    pub fn with_feed_view<I>(log: L, mut feeds: SsbFeedView) -> SsbValidatingLog<L>
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        feeds.catch_up(&log);
+        SsbValidatingLog { log, feeds }
+    }
+
+    pub fn log(&self) -> &L {
+        &self.log
+    }
+
+    pub fn feeds(&self) -> &SsbFeedView {
+        &self.feeds
+    }
+
+    pub fn into_inner(self) -> (L, SsbFeedView) {
+        (self.log, self.feeds)
+    }
+
+    /// Checks that the message `value` could be appended, returning its key.
+    pub fn validate(&self, value: &[u8]) -> Result<String, Error> {
+        self.check(&parse(value)?)
+    }
+
+    /// Validates the message `value`, then appends it to the log with the
+    /// received timestamp `timestamp`.
+    pub fn append(&mut self, value: &[u8], timestamp: u64) -> Result<Sequence, Error> {
+        let msg = parse(value)?;
+        let key = self.check(&msg)?;
+
+        let mut entry = String::from("{\"key\":");
+        write_str(&mut entry, &key);
+        entry.push_str(",\"value\":");
+        write_json(&mut entry, &msg, false, 0);
+        entry.push_str(&format!(",\"timestamp\":{}}}", timestamp));
+
+        let seq = self.log.append(entry.as_bytes())?;
+        self.feeds.append(seq, entry.as_bytes());
+        Ok(seq)
+    }
+
+    fn check(&self, msg: &Json) -> Result<String, Error> {
+        let fields = check_fields(msg)?;
+        let key = message_key(msg);
+
+        let author = fields.author;
+        let expected = self.feeds.latest_sequence(author).unwrap_or(0) + 1;
+        if fields.sequence != expected {
+            return Err(SsbValidationError::SequenceGap {
+                expected,
+                found: fields.sequence,
+            }
+            .into());
+        }
+
+        let expected_previous = match self.feeds.get(author, expected - 1) {
+            Some(seq) => Some(entry_key(&self.log.get(seq)?)?),
+            None => None,
+        };
+        if fields.previous != expected_previous.as_deref() {
+            return Err(SsbValidationError::PreviousMismatch {
+                expected: expected_previous,
+                found: fields.previous.map(|p| p.to_string()),
+            }
+            .into());
+        }
+        Ok(key)
+    }
+}
+
+/// The fields of a message that depend on the rest of its feed.
+struct FeedFields<'a> {
+    author: &'a str,
+    sequence: u64,
+    previous: Option<&'a str>,
+}
+
+/// Checks everything about `msg` that doesn't depend on the rest of its feed.
+fn check_fields(msg: &Json) -> Result<FeedFields<'_>, SsbValidationError> {
+    use SsbValidationError::*;
+
+    let fields = match msg {
+        Json::Object(fields) => fields,
+        _ => return Err(NotAnObject {}),
+    };
+    let names: Vec<&str> = fields.iter().map(|(name, _)| name.as_str()).collect();
+    let expected = [
+        "previous",
+        "author",
+        "sequence",
+        "timestamp",
+        "hash",
+        "content",
+        "signature",
+    ];
+    let swapped = [
+        "previous",
+        "sequence",
+        "author",
+        "timestamp",
+        "hash",
+        "content",
+        "signature",
+    ];
+    if names != expected && names != swapped {
+        return Err(NotCanonical {});
+    }
+    let field = |name| &fields.iter().find(|(n, _)| n == name).unwrap().1;
+
+    let author = match field("author") {
+        Json::String(author) => author,
+        _ => return Err(InvalidField { field: "author" }),
+    };
+    let author_key = match Multikey::from_legacy(author.as_bytes()) {
+        Ok((key, [])) => key,
+        _ => return Err(InvalidField { field: "author" }),
+    };
+
+    let sequence = match field("sequence") {
+        Json::Number(n) => n.as_u64().filter(|n| *n > 0),
+        _ => None,
+    }
+    .ok_or(InvalidField { field: "sequence" })?;
+
+    let previous = match field("previous") {
+        Json::Null if sequence == 1 => None,
+        Json::String(previous) if sequence > 1 && is_message_id(previous) => {
+            Some(previous.as_str())
+        }
+        _ => return Err(InvalidField { field: "previous" }),
+    };
+
+    if !matches!(field("timestamp"), Json::Number(_)) {
+        return Err(InvalidField { field: "timestamp" });
+    }
+    if !matches!(field("hash"), Json::String(hash) if hash == "sha256") {
+        return Err(InvalidField { field: "hash" });
+    }
+    let content_ok = match field("content") {
+        Json::Object(content) => content.iter().any(|(name, ty)| {
+            name == "type" && matches!(ty, Json::String(ty) if (3..=52).contains(&ty.len()))
+        }),
+        Json::String(boxed) => boxed.ends_with(".box"),
+        _ => false,
+    };
+    if !content_ok {
+        return Err(InvalidField { field: "content" });
+    }
+
+    let signed = encode(msg, true);
+    let size = signed.encode_utf16().count();
+    if size > MAX_MESSAGE_SIZE {
+        return Err(TooLarge { size });
+    }
+
+    let signature = match field("signature") {
+        Json::String(signature) => author_key.sig_from_legacy(signature.as_bytes()),
+        _ => return Err(InvalidField { field: "signature" }),
+    };
+    let signature = match signature {
+        Ok((signature, [])) => signature,
+        _ => return Err(InvalidField { field: "signature" }),
+    };
+    let unsigned = Json::Object(fields[..fields.len() - 1].to_vec());
+    if !author_key.is_signature_correct(encode(&unsigned, true).as_bytes(), &signature) {
+        return Err(InvalidSignature {});
+    }
+
+    Ok(FeedFields {
+        author,
+        sequence,
+        previous,
+    })
+}
+
+fn parse(value: &[u8]) -> Result<Json, Error> {
+    Ok(serde_json::from_slice(value)?)
+}
+
+fn is_message_id(s: &str) -> bool {
+    matches!(Multihash::from_legacy(s.as_bytes()), Ok((Multihash::Message(_), rest)) if rest.is_empty())
+}
+
+/// The key of the already validated message `msg`: the hash of its
+/// canonical encoding.
+fn message_key(msg: &Json) -> String {
+    // Node hashes the encoding as "binary", keeping only the low byte of
+    // each UTF-16 code unit.
+    let bytes: Vec<u8> = encode(msg, true).encode_utf16().map(|u| u as u8).collect();
+    Multihash::Message(ssb_crypto::hash(&bytes).0).to_legacy_string()
+}
+
+/// The `key` of a stored `{"key", "value", "timestamp"}` entry.
+fn entry_key(entry: &[u8]) -> Result<String, Error> {
+    let entry: serde_json::Value = serde_json::from_slice(entry)?;
+    if !entry.is_object() {
+        return Err(SsbValidationError::NotAnObject {}.into());
+    }
+    match entry["key"].as_str() {
+        Some(key) => Ok(key.to_string()),
+        None => Err(SsbValidationError::InvalidField { field: "key" }.into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::*;
+    use crate::mem_log::MemLog;
+    use crate::offset_log::OffsetLog;
+    use crate::ssb_key_view::SsbKeyView;
+    use crate::ssb_validating_log::*;
+    use ssb_crypto::Keypair;
+    use ssb_multiformats::multikey::Multisig;
+    use tempfile::tempfile;
+
+    struct Feed {
+        keys: Keypair,
+        previous: Option<String>,
+        sequence: u64,
+    }
+
+    impl Feed {
+        fn new(seed: u8) -> Feed {
+            Feed {
+                keys: Keypair::from_seed(&[seed; 32]).unwrap(),
+                previous: None,
+                sequence: 0,
+            }
+        }
+
+        fn id(&self) -> String {
+            Multikey::from_ed25519(&self.keys.public.0).to_legacy_string()
+        }
+
+        /// The next message in the feed, built and signed like the JS
+        /// `ssb-validate` `create` does.
+        fn next(&mut self, content: &str) -> Vec<u8> {
+            self.sequence += 1;
+            let previous = match &self.previous {
+                Some(p) => format!("\"{}\"", p),
+                None => "null".to_string(),
+            };
+            let unsigned = format!(
+                r#"{{"previous":{},"author":"{}","sequence":{},"timestamp":1553532843013.002,"hash":"sha256","content":{}}}"#,
+                previous,
+                self.id(),
+                self.sequence,
+                content
+            );
+            let msg = self.sign(&unsigned);
+            self.previous = Some(message_key(&parse(&msg).unwrap()));
+            msg
+        }
+
+        fn sign(&self, unsigned: &str) -> Vec<u8> {
+            let unsigned: Json = serde_json::from_str(unsigned).unwrap();
+            let sig = self.keys.sign(encode(&unsigned, true).as_bytes());
+            let mut fields = match unsigned {
+                Json::Object(fields) => fields,
+                _ => unreachable!(),
+            };
+            let sig = Multisig::from_ed25519(&sig.0).to_legacy_string();
+            fields.push(("signature".to_string(), Json::String(sig)));
+            encode(&Json::Object(fields), false).into_bytes()
+        }
+    }
+
+    fn validation_error(result: Result<Sequence, Error>) -> SsbValidationError {
+        match result {
+            Err(Error::SsbValidation(e)) => e,
+            r => panic!("expected a validation error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn appends_valid_feeds() -> Result<(), Error> {
+        let mut log = SsbValidatingLog::new(OffsetLog::<u32>::from_file(tempfile()?)?);
+        let (mut alice, mut bob) = (Feed::new(1), Feed::new(2));
+
+        let a1 = alice.next(r#"{"type":"post","text":"hi"}"#);
+        let b1 = bob.next(r#"{"type":"about","name":"bob"}"#);
+        let a2 = alice.next(r#""c2VjcmV0.box""#);
+        let seqs = [
+            log.append(&a1, 1)?,
+            log.append(&b1, 2)?,
+            log.append(&a2, 3)?,
+        ];
+
+        assert_eq!(log.feeds().latest_sequence(&alice.id()), Some(2));
+        assert_eq!(log.feeds().latest_sequence(&bob.id()), Some(1));
+
+        // The stored entries are what the JS ssb-db stores.
+        let mut keys = SsbKeyView::new();
+        keys.catch_up(log.log());
+        let entry: serde_json::Value = serde_json::from_slice(&log.log().get(seqs[2])?)?;
+        assert_eq!(entry["key"], alice.previous.clone().unwrap());
+        assert_eq!(entry["timestamp"], 3);
+        assert_eq!(keys.get(&alice.previous.unwrap()), Some(seqs[2]));
+
+        // Reopening the log picks up where the feeds left off.
+        let (log, _) = log.into_inner();
+        let mut log = SsbValidatingLog::new(log);
+        let b2 = bob.next(r#"{"type":"post","text":"still here"}"#);
+        assert_eq!(log.validate(&b2)?, bob.previous.clone().unwrap());
+        log.append(&b2, 4)?;
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_invalid_messages() -> Result<(), Error> {
+        let mut log = SsbValidatingLog::new(MemLog::new());
+        let mut alice = Feed::new(1);
+        let a1 = alice.next(r#"{"type":"post"}"#);
+        let a2 = alice.next(r#"{"type":"post"}"#);
+        let a3 = alice.next(r#"{"type":"post"}"#);
+
+        assert_eq!(
+            validation_error(log.append(&a2, 0)),
+            SsbValidationError::SequenceGap {
+                expected: 1,
+                found: 2
+            }
+        );
+        log.append(&a1, 0)?;
+        assert_eq!(
+            validation_error(log.append(&a1, 0)),
+            SsbValidationError::SequenceGap {
+                expected: 2,
+                found: 1
+            }
+        );
+        assert_eq!(
+            validation_error(log.append(&a3, 0)),
+            SsbValidationError::SequenceGap {
+                expected: 2,
+                found: 3
+            }
+        );
+
+        // A fork: a validly signed second message that doesn't follow the first.
+        let mut fork = Feed::new(1);
+        fork.next(r#"{"type":"other"}"#);
+        let fork2 = fork.next(r#"{"type":"post"}"#);
+        match validation_error(log.append(&fork2, 0)) {
+            SsbValidationError::PreviousMismatch { expected, found } => {
+                assert_ne!(expected, found);
+                assert!(expected.is_some());
+            }
+            e => panic!("expected PreviousMismatch, got {:?}", e),
+        }
+
+        let tampered = String::from_utf8(a2.clone())
+            .unwrap()
+            .replace("\"post\"", "\"p0st\"");
+        assert_eq!(
+            validation_error(log.append(tampered.as_bytes(), 0)),
+            SsbValidationError::InvalidSignature {}
+        );
+
+        let reordered = String::from_utf8(a2.clone())
+            .unwrap()
+            .replace("\"hash\":\"sha256\",", "")
+            .replace("\"content\"", "\"hash\":\"sha256\",\"content\"")
+            .replacen("{\"previous\"", "{\"hash\":\"sha256\",\"previous\"", 1);
+        assert_eq!(
+            validation_error(log.append(reordered.as_bytes(), 0)),
+            SsbValidationError::NotCanonical {}
+        );
+
+        let mut bob = Feed::new(2);
+        let bad_type = bob.next(r#"{"type":"x"}"#);
+        assert_eq!(
+            validation_error(log.append(&bad_type, 0)),
+            SsbValidationError::InvalidField { field: "content" }
+        );
+        let mut bob = Feed::new(2);
+        let big = bob.next(&format!(
+            r#"{{"type":"post","text":"{}"}}"#,
+            "a".repeat(9000)
+        ));
+        assert!(matches!(
+            validation_error(log.append(&big, 0)),
+            SsbValidationError::TooLarge { .. }
+        ));
+
+        assert_eq!(
+            validation_error(log.append(b"[1, 2]", 0)),
+            SsbValidationError::NotAnObject {}
+        );
+        assert!(matches!(log.append(b"not json", 0), Err(Error::Json(_))));
+
+        // Only the one valid message made it into the log.
+        assert_eq!(log.log().latest(), Some(0));
+        Ok(())
+    }
+
+    #[test]
+    fn reads_entry_keys() {
+        let key = |entry: &[u8]| match entry_key(entry) {
+            Err(Error::SsbValidation(e)) => Err(e),
+            r => Ok(r.unwrap()),
+        };
+        assert_eq!(key(br#"{"key": "%a.sha256"}"#), Ok("%a.sha256".to_string()));
+        assert_eq!(
+            key(br#"{"value": {}}"#),
+            Err(SsbValidationError::InvalidField { field: "key" })
+        );
+        assert_eq!(key(b"[1]"), Err(SsbValidationError::NotAnObject {}));
+        assert_eq!(
+            SsbValidationError::TooLarge { size: 9000 }.to_string(),
+            "Message is 9000 bytes long, more than the limit of 8192 bytes"
+        );
+    }
+
+    #[test]
+    fn hashes_like_node() {
+        // Node's "binary" encoding drops the high byte of each UTF-16 unit,
+        // so these two messages hash the same.
+        let a: Json = serde_json::from_str(r#"{"text": "Ł"}"#).unwrap();
+        let b: Json = serde_json::from_str(r#"{"text": "A"}"#).unwrap();
+        assert_eq!(message_key(&a), message_key(&b));
+        assert_ne!(encode(&a, true), encode(&b, true));
+    }
+}
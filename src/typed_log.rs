@@ -0,0 +1,171 @@
+use crate::codec::Codec;
+use crate::flume_log::*;
+use crate::iter_at_offset::IterAtOffset;
+use crate::log_entry::LogEntry;
+
+/// A log of typed values, encoded to and decoded from bytes with a [`Codec`].
+///
+/// ```
+/// use flumedb::codec::JsonCodec;
+/// use flumedb::{MemLog, TypedLog};
+/// use serde_json::{json, Value};
+///
+/// let mut log = TypedLog::new(MemLog::new(), JsonCodec::<Value>::new());
+/// let seq = log.append(&json!({"value": 1})).unwrap();
+/// assert_eq!(log.get(seq).unwrap()["value"], 1);
+/// ```
+pub struct TypedLog<L, C> {
+    log: L,
+    codec: C,
+}
+
+impl<L, C> TypedLog<L, C> {
+    pub fn new(log: L, codec: C) -> TypedLog<L, C> {
+        TypedLog { log, codec }
+    }
+
+    pub fn log(&self) -> &L {
+        &self.log
+    }
+
+    pub fn log_mut(&mut self) -> &mut L {
+        &mut self.log
+    }
+
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    pub fn into_inner(self) -> L {
+        self.log
+    }
+}
+
+impl<L: FlumeLog, C: Codec> TypedLog<L, C> {
+    pub fn get(&self, seq: Sequence) -> Result<C::Item, Error> {
+        self.codec.decode(&self.log.get(seq)?)
+    }
+
+    /// Reads and decodes the entries at `seqs`, like [`FlumeLog::get_many`].
+    pub fn get_many(&self, seqs: &[Sequence]) -> Vec<Result<C::Item, Error>> {
+        self.log
+            .get_many(seqs)
+            .into_iter()
+            .map(|data| self.codec.decode(&data?))
+            .collect()
+    }
+
+    pub fn latest(&self) -> Option<Sequence> {
+        self.log.latest()
+    }
+
+    pub fn append(&mut self, item: &C::Item) -> Result<Sequence, Error> {
+        let bytes = self.codec.encode(item)?;
+        self.log.append(&bytes)
+    }
+}
+
+impl<L, C: Codec> TypedLog<L, C> {
+    /// Iterates over every entry in the log, decoding each one.
+    pub fn iter<I>(&self) -> TypedLogIter<'_, I, C>
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        self.iter_at_offset(0)
+    }
+
+    pub fn iter_at_offset<I>(&self, offset: u64) -> TypedLogIter<'_, I, C>
+    where
+        L: IterAtOffset<I>,
+        I: Iterator<Item = LogEntry>,
+    {
+        self.decode_iter(self.log.iter_at_offset(offset))
+    }
+
+    /// Decodes the entries of any iterator over this log, such as
+    /// `OffsetLog::bidir_iter`.
+    pub fn decode_iter<I>(&self, iter: I) -> TypedLogIter<'_, I, C>
+    where
+        I: Iterator<Item = LogEntry>,
+    {
+        TypedLogIter {
+            iter,
+            codec: &self.codec,
+        }
+    }
+}
+
+/// Yields the sequence and decoded value of each entry, or the decoding error.
+pub struct TypedLogIter<'a, I, C> {
+    iter: I,
+    codec: &'a C,
+}
+
+impl<'a, I, C> Iterator for TypedLogIter<'a, I, C>
+where
+    I: Iterator<Item = LogEntry>,
+    C: Codec,
+{
+    type Item = Result<(Sequence, C::Item), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next().map(|entry| {
+            self.codec
+                .decode(&entry.data)
+                .map(|item| (entry.offset, item))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::codec::*;
+    use crate::offset_log::OffsetLog;
+    use crate::typed_log::*;
+    use serde_json::{json, Value};
+    use tempfile::tempfile;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        value: u64,
+    }
+
+    #[test]
+    fn read_js_json_log() {
+        let log = OffsetLog::<u32>::open_read_only("./db/test.offset").unwrap();
+        let log = TypedLog::new(log, JsonCodec::<Item>::new());
+
+        let values: Vec<u64> = log.iter().map(|r| r.unwrap().1.value).take(5).collect();
+        assert_eq!(values, &[0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn append_and_get() -> Result<(), Error> {
+        let log = OffsetLog::<u32>::from_file(tempfile()?)?;
+        let mut log = TypedLog::new(log, CborCodec::<Item>::new());
+
+        let a = log.append(&Item { value: 1 })?;
+        let b = log.append(&Item { value: 2 })?;
+        assert_eq!(log.latest(), Some(b));
+        assert_eq!(log.get(a)?, Item { value: 1 });
+
+        let items: Vec<(u64, Item)> = log.iter().collect::<Result<_, _>>()?;
+        assert_eq!(items, vec![(a, Item { value: 1 }), (b, Item { value: 2 })]);
+        Ok(())
+    }
+
+    #[test]
+    fn decode_errors_are_yielded() -> Result<(), Error> {
+        let mut log = OffsetLog::<u32>::from_file(tempfile()?)?;
+        log.append(b"{\"value\": 1}")?;
+        log.append(b"not json")?;
+        let log = TypedLog::new(log, JsonCodec::<Value>::new());
+
+        let mut iter = log.decode_iter(log.log().iter());
+        assert_eq!(iter.next().unwrap()?.1, json!({"value": 1}));
+        assert!(iter.next().unwrap().is_err());
+        assert!(iter.next().is_none());
+        Ok(())
+    }
+}